@@ -1,17 +1,270 @@
-use tauri::{AppHandle, Emitter, Manager, State};
-use tokio::sync::Mutex;
+use jsonwebtoken::{DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
 
-use crate::types::AppState;
+use crate::{
+    constants,
+    error::{self, Error, Result},
+    types::AppState,
+    util::{parse_access_token, phrase},
+    websocket::start_ws_client,
+};
 
-// use crate::{constants::CONFIG_FILE_NAME, types::ConfigFile};
+/// The identity this client ended up with.
+#[derive(Serialize, Clone, Debug)]
+pub struct Identity {
+    pub id: String,
+    /// False when a new identity had to be registered.
+    pub restored: bool,
+}
+
+#[derive(Deserialize)]
+struct Registration {
+    access_token: String,
+    recovery_key: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    id: String,
+}
+
+/// Steps of the onboarding, emitted as `auth:progress`.
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Step {
+    /// Renewing the stored token.
+    Restoring,
+    /// The server rejected the stored token.
+    Expired,
+    /// Trading the stored recovery key for a new token.
+    Recovering,
+    Registering,
+    Saving,
+}
+
+fn progress(app: &AppHandle, step: Step) {
+    if let Err(err) = app.emit("auth:progress", serde_json::json!({ "step": step })) {
+        eprintln!("Failed to emit auth progress: {}", err);
+    }
+}
+
+async fn register() -> Result<Registration> {
+    let response = reqwest::Client::new()
+        .post("http://localhost:8080/client/auth")
+        .send()
+        .await?;
+
+    Ok(error::check(response).await?.json().await?)
+}
+
+async fn restore(token: String) -> Result<String> {
+    let response = reqwest::Client::new()
+        .post("http://localhost:8080/client/refresh")
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    parse_access_token(response).await
+}
+
+async fn recover(key: &str) -> Result<String> {
+    let response = reqwest::Client::new()
+        .post("http://localhost:8080/client/recover")
+        .json(&serde_json::json!({ "recovery_key": key }))
+        .send()
+        .await?;
+
+    parse_access_token(response).await
+}
+
+/// Reads the client id out of a token the server just issued. The signature
+/// can only be checked by the server.
+fn identity_of(token: &str) -> Result<String> {
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+
+    jsonwebtoken::decode::<Claims>(token, &DecodingKey::from_secret(&[]), &validation)
+        .map(|data| data.claims.id)
+        .map_err(|e| Error::InvalidResponse(format!("Invalid access token: {}", e)))
+}
+
+/// Persists a token and, for a new registration, its recovery key.
+async fn persist(app: &AppHandle, token: String, recovery_key: Option<String>) -> Result<()> {
+    let state = app.state::<AppState>();
+    let path = app.path().app_data_dir()?.join(constants::CONFIG_FILE_NAME);
+
+    if let Some(key) = recovery_key {
+        state.set_recovery_key(key, path.clone()).await?;
+    }
+    state.set_access_token(token, path).await
+}
+
+async fn register_new(app: &AppHandle) -> Result<Identity> {
+    progress(app, Step::Registering);
+    let registration = register().await?;
+
+    let identity = Identity {
+        id: identity_of(&registration.access_token)?,
+        restored: false,
+    };
+
+    progress(app, Step::Saving);
+    persist(
+        app,
+        registration.access_token,
+        Some(registration.recovery_key),
+    )
+    .await?;
+
+    Ok(identity)
+}
+
+/// Renews the stored identity and persists the new token. When the server
+/// rejects the stored token, e.g. after a long time offline, the stored
+/// recovery key is traded for a new one. A new identity is only registered
+/// when nothing is stored; one that cannot be restored is reported as
+/// `Unauthorized` and replacing it is up to the user.
+pub async fn onboard(app: &AppHandle) -> Result<Identity> {
+    let state = app.state::<AppState>();
+    let stored = state.get_access_token().await;
+    let recovery_key = state.recovery_key().await;
+
+    if stored.is_none() && recovery_key.is_none() {
+        return register_new(app).await;
+    }
+
+    let renewed = match stored {
+        Some(token) => {
+            progress(app, Step::Restoring);
+
+            match restore(token).await {
+                Err(Error::Unauthorized) => {
+                    progress(app, Step::Expired);
+                    None
+                }
+                result => Some(result?),
+            }
+        }
+        None => None,
+    };
+
+    let token = match (renewed, recovery_key) {
+        (Some(token), _) => token,
+        (None, Some(key)) => {
+            progress(app, Step::Recovering);
+            recover(&key).await?
+        }
+        (None, None) => return Err(Error::Unauthorized),
+    };
+
+    let identity = Identity {
+        id: identity_of(&token)?,
+        restored: true,
+    };
+
+    progress(app, Step::Saving);
+    persist(app, token, None).await?;
+
+    Ok(identity)
+}
 
 #[tauri::command]
-pub async fn authenticate(
-    app: AppHandle,
-    state: State<'_, Mutex<AppState>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let path = app.path().app_data_dir()?;
-
-    app.emit("auth:success", ());
-    todo!()
+pub async fn authenticate(app: AppHandle) -> Result<Identity> {
+    match onboard(&app).await {
+        Ok(identity) => {
+            if let Err(err) = app.emit("auth:success", identity.clone()) {
+                eprintln!("Failed to emit auth success: {}", err);
+            }
+            Ok(identity)
+        }
+        Err(err) => {
+            if let Err(emit_err) = app.emit("auth:failed", &err) {
+                eprintln!("Failed to emit auth failure: {}", emit_err);
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Registers a new identity in place of the stored one. Only meant to be
+/// called on the user's request, e.g. after onboarding failed with
+/// `Unauthorized`, since the stored identity is lost unless it is restored
+/// from its recovery phrase.
+#[tauri::command]
+pub async fn register_identity(app: AppHandle) -> Result<Identity> {
+    let identity = register_new(&app).await?;
+
+    if let Err(err) = app.emit("auth:success", identity.clone()) {
+        eprintln!("Failed to emit auth success: {}", err);
+    }
+
+    Ok(identity)
+}
+
+/// The recovery key of this identity as a phrase. Identities registered
+/// before recovery keys existed get one issued first.
+#[tauri::command]
+pub async fn show_recovery_phrase(app: AppHandle) -> Result<String> {
+    let state = app.state::<AppState>();
+
+    let key = match state.recovery_key().await {
+        Some(key) => key,
+        None => {
+            let token = state
+                .get_access_token()
+                .await
+                .ok_or(Error::NotAuthenticated)?;
+
+            let response = reqwest::Client::new()
+                .post("http://localhost:8080/api/self/recovery-key")
+                .bearer_auth(token)
+                .send()
+                .await?;
+
+            let key = error::check(response)
+                .await?
+                .json::<serde_json::Value>()
+                .await?["recovery_key"]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| Error::InvalidResponse("recovery_key is missing".into()))?;
+
+            state
+                .set_recovery_key(
+                    key.clone(),
+                    app.path().app_data_dir()?.join(constants::CONFIG_FILE_NAME),
+                )
+                .await?;
+            key
+        }
+    };
+
+    phrase::to_phrase(&key)
+}
+
+/// Replaces the current identity with the one the phrase was issued to and
+/// reconnects as it.
+#[tauri::command]
+pub async fn restore_identity(app: AppHandle, phrase: String) -> Result<Identity> {
+    let key = phrase::from_phrase(&phrase)?;
+
+    let token = recover(&key).await?;
+    let identity = Identity {
+        id: identity_of(&token)?,
+        restored: true,
+    };
+
+    persist(&app, token, Some(key)).await?;
+
+    if let Err(err) = app.emit("auth:success", identity.clone()) {
+        eprintln!("Failed to emit auth success: {}", err);
+    }
+
+    if !app.state::<AppState>().change_identity() {
+        let handle = app.clone();
+        tauri::async_runtime::spawn(async move { start_ws_client(handle, true).await });
+    }
+
+    Ok(identity)
 }
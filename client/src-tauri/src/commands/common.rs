@@ -1,6 +1,13 @@
 use tauri::{AppHandle, Manager};
 
+use crate::error::{Error, Result};
+
 #[tauri::command]
-pub fn show_main_window(window: AppHandle) {
-    window.get_webview_window("main").unwrap().show().unwrap();
+pub fn show_main_window(window: AppHandle) -> Result<()> {
+    window
+        .get_webview_window("main")
+        .ok_or(Error::MissingWindow("main"))?
+        .show()?;
+
+    Ok(())
 }
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use uuid::Uuid;
+
+use crate::{
+    error::{self, Error, Result},
+    types::AppState,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublicUser {
+    pub id: String,
+    pub handle: Option<String>,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_url: String,
+    pub is_contact: bool,
+    pub online: Option<bool>,
+}
+
+#[tauri::command]
+pub async fn lookup_user(app: AppHandle, query: String) -> Result<Option<PublicUser>> {
+    let app_s = app.state::<AppState>();
+    let token = app_s
+        .get_access_token()
+        .await
+        .ok_or(Error::NotAuthenticated)?;
+
+    let response = reqwest::Client::new()
+        .get("http://localhost:8080/api/users/lookup")
+        .query(&[("q", query)])
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    match error::check(response).await {
+        Ok(response) => Ok(Some(response.json::<PublicUser>().await?)),
+        Err(Error::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[tauri::command]
+pub async fn send_contact_request(app: AppHandle, id: String) -> Result<()> {
+    let app_s = app.state::<AppState>();
+    let token = app_s
+        .get_access_token()
+        .await
+        .ok_or(Error::NotAuthenticated)?;
+
+    let response = reqwest::Client::new()
+        .post("http://localhost:8080/api/contacts/requests")
+        .bearer_auth(token)
+        .json(&serde_json::json!({ "id": id }))
+        .send()
+        .await?;
+
+    error::check(response).await?;
+    Ok(())
+}
+
+/// Avatars need authentication, so the frontend loads them through this
+/// rather than as image sources.
+#[tauri::command]
+pub async fn fetch_avatar(
+    app: AppHandle,
+    id: String,
+    size: Option<u32>,
+) -> Result<tauri::ipc::Response> {
+    // Parsed so the url cannot point anywhere but an avatar.
+    let id = Uuid::parse_str(&id).map_err(|_| Error::BadRequest("Invalid user id".into()))?;
+
+    let app_s = app.state::<AppState>();
+    let token = app_s
+        .get_access_token()
+        .await
+        .ok_or(Error::NotAuthenticated)?;
+
+    let mut request =
+        reqwest::Client::new().get(format!("http://localhost:8080/api/users/{}/avatar", id));
+    if let Some(size) = size {
+        request = request.query(&[("size", size)]);
+    }
+
+    let response = request.bearer_auth(token).send().await?;
+
+    let bytes = error::check(response).await?.bytes().await?;
+    Ok(tauri::ipc::Response::new(bytes.to_vec()))
+}
@@ -1,9 +1,16 @@
 use tauri::{AppHandle, Manager};
 
-use crate::{constants, types::AppState, websocket::start_ws_client};
+use crate::{
+    constants,
+    error::{Error, Result},
+    types::AppState,
+    util::parse_access_token,
+    websocket::start_ws_client,
+};
 
 pub mod auth;
 pub mod common;
+pub mod contacts;
 
 #[tauri::command]
 pub fn connect_ws(app: AppHandle) {
@@ -13,36 +20,27 @@ pub fn connect_ws(app: AppHandle) {
 }
 
 #[tauri::command]
-pub async fn change_id_request(app: AppHandle) -> Result<(), String> {
+pub async fn change_id_request(app: AppHandle) -> Result<()> {
     let app_s = app.state::<AppState>();
+    let token = app_s
+        .get_access_token()
+        .await
+        .ok_or(Error::NotAuthenticated)?;
 
     let response = reqwest::Client::new()
         .post("http://localhost:8080/api/self/changeid")
-        .header("Authorization", app_s.get_access_token().await.unwrap())
+        .bearer_auth(token)
         .send()
-        .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
+        .await?;
 
-    println!("Response: {:?}", response.status());
-
-    let token = response
-        .json::<serde_json::Value>()
-        .await
-        .map_err(|e| format!("Failed to serialize server response {}", e))?["access_token"]
-        .as_str()
-        .unwrap()
-        .to_string();
+    let token = parse_access_token(response).await?;
 
     app_s
         .set_access_token(
             token,
-            app.path()
-                .app_data_dir()
-                .map_err(|e| format!("Failed to get app data dir: {}", e))?
-                .join(constants::CONFIG_FILE_NAME),
+            app.path().app_data_dir()?.join(constants::CONFIG_FILE_NAME),
         )
-        .await
-        .map_err(|e| format!("failed to set access token {:?}", e))?;
+        .await?;
 
     Ok(())
 }
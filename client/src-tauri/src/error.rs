@@ -0,0 +1,192 @@
+use serde::{ser::SerializeStruct, Deserialize, Serialize};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors returned to the frontend, serialized as `{kind, message}`.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Not authenticated")]
+    NotAuthenticated,
+
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    #[error("Forbidden")]
+    Forbidden,
+
+    #[error("Not found")]
+    NotFound,
+
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    #[error("Too many requests, try again later")]
+    RateLimited,
+
+    /// The server failed, or answered with a code this client does not know.
+    #[error("{0}")]
+    Server(String),
+
+    #[error("Failed to reach the server: {0}")]
+    Network(#[from] reqwest::Error),
+
+    #[error("Unexpected server response: {0}")]
+    InvalidResponse(String),
+
+    #[error("WebSocket error: {0}")]
+    WebSocket(#[from] tungstenite::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("Failed to read or write the config file: {0}")]
+    Config(#[from] postcard::Error),
+
+    #[error(transparent)]
+    Tauri(#[from] tauri::Error),
+
+    #[error("Window {0} not found")]
+    MissingWindow(&'static str),
+}
+
+impl Error {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::NotAuthenticated => "not_authenticated",
+            Error::Unauthorized => "unauthorized",
+            Error::Forbidden => "forbidden",
+            Error::NotFound => "not_found",
+            Error::BadRequest(_) => "bad_request",
+            Error::Conflict(_) => "conflict",
+            Error::RateLimited => "rate_limited",
+            Error::Server(_) => "server",
+            Error::Network(_) => "network",
+            Error::InvalidResponse(_) => "invalid_response",
+            Error::WebSocket(_) => "websocket",
+            Error::Json(_) => "json",
+            Error::Io(_) => "io",
+            Error::Config(_) => "config",
+            Error::Tauri(_) => "tauri",
+            Error::MissingWindow(_) => "missing_window",
+        }
+    }
+
+    /// Maps a code of the server's error envelope.
+    pub fn from_code(code: &str, message: String) -> Self {
+        match code {
+            "unauthorized" | "invalid_token" => Error::Unauthorized,
+            "forbidden" => Error::Forbidden,
+            "not_found" => Error::NotFound,
+            "rate_limited" => Error::RateLimited,
+            "conflict" | "upload_incomplete" => Error::Conflict(message),
+            "bad_request"
+            | "invalid_json"
+            | "invalid_image"
+            | "method_not_allowed"
+            | "payload_too_large"
+            | "unsupported_media_type" => Error::BadRequest(message),
+            _ => Error::Server(message),
+        }
+    }
+}
+
+impl Serialize for Error {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        let mut error = serializer.serialize_struct("Error", 2)?;
+        error.serialize_field("kind", self.kind())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.end()
+    }
+}
+
+/// The body of the server's error responses.
+#[derive(Deserialize)]
+struct ServerError {
+    code: String,
+    message: String,
+}
+
+/// Passes successful responses through and turns the others into an
+/// [`Error`].
+pub async fn check(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    match response.json::<ServerError>().await {
+        Ok(error) => Err(Error::from_code(&error.code, error.message)),
+        Err(_) => Err(Error::Server(format!(
+            "Request failed with status {}",
+            status
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_codes_map_to_variants() {
+        let cases = [
+            ("unauthorized", "unauthorized"),
+            ("invalid_token", "unauthorized"),
+            ("forbidden", "forbidden"),
+            ("not_found", "not_found"),
+            ("rate_limited", "rate_limited"),
+            ("conflict", "conflict"),
+            ("upload_incomplete", "conflict"),
+            ("bad_request", "bad_request"),
+            ("invalid_json", "bad_request"),
+            ("invalid_image", "bad_request"),
+            ("method_not_allowed", "bad_request"),
+            ("payload_too_large", "bad_request"),
+            ("unsupported_media_type", "bad_request"),
+            ("internal", "server"),
+            ("something_new", "server"),
+        ];
+
+        for (code, kind) in cases {
+            assert_eq!(
+                Error::from_code(code, "message".into()).kind(),
+                kind,
+                "{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn server_messages_are_kept() {
+        assert_eq!(
+            Error::from_code("conflict", "Request already pending".into()).to_string(),
+            "Request already pending"
+        );
+        assert_eq!(
+            Error::from_code("internal", "Internal server error".into()).to_string(),
+            "Internal server error"
+        );
+    }
+
+    #[test]
+    fn serializes_kind_and_message() {
+        assert_eq!(
+            serde_json::to_value(Error::Forbidden).unwrap(),
+            serde_json::json!({ "kind": "forbidden", "message": "Forbidden" })
+        );
+        assert_eq!(
+            serde_json::to_value(Error::MissingWindow("main")).unwrap(),
+            serde_json::json!({ "kind": "missing_window", "message": "Window main not found" })
+        );
+    }
+}
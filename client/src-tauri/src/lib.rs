@@ -2,13 +2,11 @@ use tauri::{async_runtime::Mutex, utils::config, Manager};
 use tauri_plugin_dialog::DialogExt;
 use tauri_plugin_http::reqwest;
 
-use crate::{
-    types::{AppState, ConfigFile},
-    util::get_access_token,
-};
+use crate::types::{AppState, ConfigFile};
 
 pub mod commands;
 pub mod constants;
+pub mod error;
 pub mod types;
 pub mod util;
 pub mod websocket;
@@ -23,31 +21,30 @@ pub fn run() {
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
             commands::common::show_main_window,
+            commands::auth::authenticate,
+            commands::auth::register_identity,
+            commands::auth::show_recovery_phrase,
+            commands::auth::restore_identity,
             commands::connect_ws,
-            commands::change_id_request
+            commands::change_id_request,
+            commands::contacts::lookup_user,
+            commands::contacts::send_contact_request,
+            commands::contacts::fetch_avatar
         ])
         .setup(|app| {
             let handle = app.handle().clone();
 
-            let path = app.path().app_data_dir()?;
-            match path.join(constants::CONFIG_FILE_NAME).exists() {
-                true => {
-                    let config: ConfigFile =
-                        types::ConfigFile::load(path.join(constants::CONFIG_FILE_NAME))?;
+            // Nothing here may wait on the server. Registering or renewing the
+            // identity happens in `commands::auth::onboard` once the frontend
+            // is up and can show progress.
+            let path = app.path().app_data_dir()?.join(constants::CONFIG_FILE_NAME);
+            let config = match path.exists() {
+                true => ConfigFile::load(path)?,
+                false => ConfigFile::default(),
+            };
 
-                    println!("Loaded config: {:?}", config);
-                    let access_token = config
-                        .access_token()
-                        .and_then(|token| Some(token.to_owned()));
-                    app.manage(AppState::new(access_token, config));
-                }
-                false => {
-                    let token = get_access_token()?;
-                    let config = types::ConfigFile::new(Some(token.clone()));
-                    config.save(path.join(constants::CONFIG_FILE_NAME))?;
-                    app.manage(AppState::new(Some(token), config));
-                }
-            }
+            let access_token = config.access_token().map(str::to_owned);
+            app.manage(AppState::new(access_token, config));
 
             tauri::async_runtime::spawn(async move {
                 websocket::start_ws_client(handle, false).await;
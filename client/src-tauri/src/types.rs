@@ -9,31 +9,54 @@ use serde::{Deserialize, Serialize};
 use tauri::{
     async_runtime::Mutex, webview::cookie::time::format_description::well_known::iso8601::Config,
 };
-use tokio::sync::MutexGuard;
+use tokio::sync::{watch, MutexGuard};
 
-use crate::{constants::CONFIG_FILE_NAME, util::get_access_token};
+use crate::{constants::CONFIG_FILE_NAME, error::Result};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ConfigFile {
     access_token: Option<String>,
+    recovery_key: Option<String>,
+}
+
+/// The config as written before recovery keys. postcard is not
+/// self-describing, so old files only decode with the old layout.
+#[derive(Deserialize)]
+struct LegacyConfigFile {
+    access_token: Option<String>,
 }
 
 impl ConfigFile {
     pub fn new(access_token: Option<String>) -> Self {
-        Self { access_token }
+        Self {
+            access_token,
+            recovery_key: None,
+        }
     }
 
     pub fn access_token(&self) -> Option<&str> {
         self.access_token.as_deref()
     }
 
-    pub fn set_access_token(&mut self, token: String) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn set_access_token(&mut self, token: String) -> Result<()> {
         self.access_token = Some(token);
 
         Ok(())
     }
 
-    pub fn save(&self, path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn recovery_key(&self) -> Option<&str> {
+        self.recovery_key.as_deref()
+    }
+
+    pub fn set_recovery_key(&mut self, key: String) {
+        self.recovery_key = Some(key);
+    }
+
+    pub fn save(&self, path: PathBuf) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
         let mut file = File::create(path)?;
         let bytes = to_allocvec(&self)?;
         file.write_all(&bytes)?;
@@ -41,16 +64,22 @@ impl ConfigFile {
         Ok(())
     }
 
-    pub fn load(path: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+    /// A corrupt file loads as an empty config, the identity is then
+    /// registered anew during onboarding.
+    pub fn load(path: PathBuf) -> Result<Self> {
         let mut file = File::open(path)?;
         let mut bytes = Vec::new();
         file.read_to_end(&mut bytes)?;
 
-        match postcard::from_bytes(&bytes) {
-            Ok(config) => Ok(config),
+        if let Ok(config) = postcard::from_bytes(&bytes) {
+            return Ok(config);
+        }
+
+        match postcard::from_bytes::<LegacyConfigFile>(&bytes) {
+            Ok(legacy) => Ok(Self::new(legacy.access_token)),
             Err(err) => {
-                let token = get_access_token()?;
-                Ok(Self::new(Some(token)))
+                eprintln!("Ignoring corrupt config file: {}", err);
+                Ok(Self::default())
             }
         }
     }
@@ -58,7 +87,7 @@ impl ConfigFile {
 
 impl Default for ConfigFile {
     fn default() -> Self {
-        Self { access_token: None }
+        Self::new(None)
     }
 }
 
@@ -66,6 +95,9 @@ impl Default for ConfigFile {
 pub struct AppState {
     token: Mutex<Option<String>>,
     config: Mutex<ConfigFile>,
+    /// Bumped when another identity replaces the current one. The websocket
+    /// task watches it to reconnect as the new identity.
+    identity: watch::Sender<u64>,
 }
 
 impl AppState {
@@ -73,25 +105,45 @@ impl AppState {
         Self {
             token: Mutex::new(token),
             config: Mutex::new(config),
+            identity: watch::Sender::new(0),
         }
     }
 
-    pub async fn set_access_token(
-        &self,
-        token: String,
-        path: PathBuf,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn set_access_token(&self, token: String, path: PathBuf) -> Result<()> {
         let mut guard = self.token.lock().await;
         *guard = Some(token.clone());
 
         let mut guard = self.config.lock().await;
         guard.set_access_token(token.clone())?;
-        guard.save(path);
+        guard.save(path)?;
+
+        Ok(())
+    }
+
+    pub async fn recovery_key(&self) -> Option<String> {
+        self.config.lock().await.recovery_key().map(str::to_owned)
+    }
+
+    pub async fn set_recovery_key(&self, key: String, path: PathBuf) -> Result<()> {
+        let mut guard = self.config.lock().await;
+        guard.set_recovery_key(key);
+        guard.save(path)?;
 
         Ok(())
     }
 
-    pub async fn clear_access_token(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn watch_identity(&self) -> watch::Receiver<u64> {
+        self.identity.subscribe()
+    }
+
+    /// Tells the websocket task to reconnect as the stored identity. Returns
+    /// false when no task is running to do so.
+    pub fn change_identity(&self) -> bool {
+        self.identity.send_modify(|generation| *generation += 1);
+        self.identity.receiver_count() > 0
+    }
+
+    pub async fn clear_access_token(&self) -> Result<()> {
         let mut guard = self.token.lock().await;
         *guard = None;
 
@@ -1,17 +1,14 @@
-pub fn get_access_token() -> Result<String, Box<dyn std::error::Error>> {
-    let token = tauri::async_runtime::block_on(async move {
-        let response = reqwest::Client::new()
-            .post("http://localhost:8080/client/auth")
-            .send()
-            .await?;
+pub mod phrase;
 
-        if !response.status().is_success() {
-            return Err("Authentication failed".into());
-        }
+use crate::error::{self, Error, Result};
 
-        let raw_val: serde_json::Value = response.json().await?;
-        Ok(raw_val["access_token"].as_str().unwrap().to_string())
-    });
-
-    token
+/// Reads the `access_token` field of a token issuing response.
+pub async fn parse_access_token(response: reqwest::Response) -> Result<String> {
+    error::check(response)
+        .await?
+        .json::<serde_json::Value>()
+        .await?["access_token"]
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| Error::InvalidResponse("access_token is missing".into()))
 }
@@ -0,0 +1,138 @@
+//! Recovery keys shown as phrases. The server issues the key as 32 hex
+//! digits, each of its 16 bytes becomes one word of [`WORDS`].
+
+use crate::error::{Error, Result};
+
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adobe", "agent", "alarm", "album", "alien", "alpha", "amber",
+    "angle", "ankle", "apple", "april", "arena", "armor", "arrow", "atlas", "attic", "audio",
+    "award", "bacon", "badge", "bagel", "baker", "bamboo", "banjo", "barn", "basil", "beach",
+    "beard", "berry", "bison", "blade", "blank", "blaze", "bloom", "board", "bonus", "boot",
+    "brave", "bread", "brick", "bridge", "brush", "bucket", "cabin", "cable", "cactus", "camel",
+    "candy", "canoe", "canyon", "cargo", "carpet", "castle", "cedar", "chalk", "chess", "chief",
+    "cider", "cigar", "circle", "clay", "cliff", "clock", "cloud", "clover", "coast", "cobra",
+    "cocoa", "comet", "coral", "couch", "crab", "crane", "crown", "curry", "daisy", "dance",
+    "delta", "denim", "desert", "diary", "donut", "dragon", "drum", "eagle", "easel", "echo",
+    "elbow", "ember", "engine", "fable", "falcon", "ferry", "fiber", "fiddle", "flame", "flute",
+    "forest", "fossil", "fox", "frost", "galaxy", "garden", "garlic", "gecko", "ghost", "giant",
+    "ginger", "globe", "goat", "gold", "grape", "gravel", "guitar", "hammer", "harbor", "hazel",
+    "helmet", "heron", "honey", "hornet", "hotel", "igloo", "indigo", "island", "ivory", "jacket",
+    "jaguar", "jelly", "jewel", "jungle", "kayak", "kettle", "kiwi", "koala", "ladder", "lagoon",
+    "lamp", "laser", "lemon", "lilac", "lime", "linen", "lizard", "locket", "lotus", "lunar",
+    "magnet", "mango", "maple", "marble", "meadow", "melon", "metal", "meteor", "mint", "mirror",
+    "monkey", "moose", "motor", "muffin", "napkin", "nectar", "needle", "noodle", "nutmeg",
+    "oasis", "ocean", "olive", "onion", "opal", "orbit", "orchid", "otter", "oyster", "paddle",
+    "panda", "paper", "parrot", "peach", "pepper", "piano", "pillow", "pilot", "pine", "planet",
+    "plum", "polar", "pony", "poppy", "potato", "prism", "puzzle", "quartz", "quill", "rabbit",
+    "radar", "radio", "raven", "reef", "ribbon", "river", "robin", "rocket", "ruby", "saddle",
+    "salmon", "satin", "scarf", "shadow", "shell", "silver", "sketch", "sloth", "snow", "socket",
+    "sofa", "spider", "spoon", "squid", "stamp", "statue", "stone", "sugar", "summit", "sunset",
+    "swan", "tango", "teapot", "tiger", "timber", "toast", "tomato", "topaz", "torch", "tulip",
+    "tundra", "turtle", "valley", "velvet", "violin", "waffle", "walnut", "walrus", "whale",
+    "willow", "window", "winter", "wizard", "yacht", "yogurt", "zebra", "zephyr",
+];
+
+pub fn to_phrase(key: &str) -> Result<String> {
+    let invalid = || Error::InvalidResponse("Malformed recovery key".into());
+
+    if key.len() != 32 || !key.is_ascii() {
+        return Err(invalid());
+    }
+
+    (0..key.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&key[i..i + 2], 16)
+                .map(|byte| WORDS[byte as usize])
+                .map_err(|_| invalid())
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(|words| words.join(" "))
+}
+
+/// Turns a phrase back into the key the server expects. Case and extra
+/// whitespace are ignored.
+pub fn from_phrase(phrase: &str) -> Result<String> {
+    let words = phrase
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>();
+
+    if words.len() != 16 {
+        return Err(Error::BadRequest("A recovery phrase has 16 words".into()));
+    }
+
+    words
+        .iter()
+        .map(|word| {
+            WORDS
+                .binary_search(&word.as_str())
+                .map(|byte| format!("{:02x}", byte))
+                .map_err(|_| {
+                    Error::BadRequest(format!("Unknown word in recovery phrase: {}", word))
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "00ff10a0b1c2d3e4f5061728394a5b6c";
+
+    #[test]
+    fn words_are_sorted_and_unique() {
+        assert!(WORDS.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn phrases_round_trip() {
+        let phrase = to_phrase(KEY).unwrap();
+
+        assert_eq!(phrase.split(' ').count(), 16);
+        assert_eq!(from_phrase(&phrase).unwrap(), KEY);
+    }
+
+    #[test]
+    fn case_and_whitespace_are_ignored() {
+        let phrase = to_phrase(KEY).unwrap().to_uppercase().replace(' ', "  \n");
+
+        assert_eq!(from_phrase(&format!("  {}  ", phrase)).unwrap(), KEY);
+    }
+
+    #[test]
+    fn unknown_words_are_rejected() {
+        let phrase = to_phrase(KEY).unwrap().replacen("acid", "acidic", 1);
+
+        assert!(matches!(from_phrase(&phrase), Err(Error::BadRequest(_))));
+    }
+
+    #[test]
+    fn phrases_need_sixteen_words() {
+        let phrase = to_phrase(KEY).unwrap();
+        let short = phrase.rsplit_once(' ').unwrap().0;
+
+        assert!(matches!(from_phrase(short), Err(Error::BadRequest(_))));
+        assert!(matches!(
+            from_phrase(&format!("{} acid", phrase)),
+            Err(Error::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_keys_are_rejected() {
+        for key in [
+            "",
+            "00ff",
+            "zz",
+            &KEY.replace('0', "g"),
+            &format!("{}00", KEY),
+        ] {
+            assert!(
+                matches!(to_phrase(key), Err(Error::InvalidResponse(_))),
+                "{key}"
+            );
+        }
+    }
+}
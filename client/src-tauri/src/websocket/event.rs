@@ -1,14 +1,45 @@
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "UPPERCASE")]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum EventType {
     ChangeMyId,
+    /// Sent with a fresh token in answer to `AuthExpiring`.
+    Reauth,
+    Authenticated,
+    /// The socket's token expires soon and has to be replaced with `Reauth`.
+    AuthExpiring,
     MessageReceived,
+    MessagesExpired,
+    /// The server's estimate of the connection, sent after each of its pings.
+    ConnectionQuality,
+    /// Events this client does not handle yet.
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Event<D> {
-    event_type: EventType,
-    data: D,
+    pub event_type: EventType,
+    pub data: D,
+}
+
+/// Messages the server deleted because their disappearing timer ran out.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MessagesExpired {
+    pub ids: Vec<String>,
+}
+
+/// Round-trip time estimates of the server, in milliseconds.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionQuality {
+    pub smoothed_rtt_ms: f64,
+    pub jitter_ms: f64,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AuthPayload {
+    pub token: String,
 }
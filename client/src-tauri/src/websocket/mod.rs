@@ -1,24 +1,181 @@
 pub mod event;
 
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
 use serde_json::json;
 use tauri::{
     http::{HeaderValue, Request},
     AppHandle, Emitter, Listener, Manager,
 };
-use tokio::sync::Notify;
-use tokio_tungstenite::{connect_async, connect_async_with_config};
-use tungstenite::{client::IntoClientRequest, Bytes, Message};
+use tokio::{
+    net::TcpStream,
+    sync::{watch, Notify},
+};
+use tokio_tungstenite::{
+    connect_async, connect_async_with_config, MaybeTlsStream, WebSocketStream,
+};
+use tungstenite::{
+    client::IntoClientRequest,
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Bytes, Message,
+};
+
+use crate::{
+    commands, constants,
+    error::{Error, Result},
+    types::AppState,
+    util::parse_access_token,
+    websocket::event::{AuthPayload, ConnectionQuality, Event, EventType, MessagesExpired},
+};
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+/// The server is considered gone when nothing arrived for this long.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(15);
+/// How long to wait out a restart the server gave no retry hint for.
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+/// Reconnect attempts after losing the server back off from the first delay
+/// up to the second.
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+/// Our pings carry the microseconds elapsed since `epoch`, so the matching
+/// pong tells the round-trip time.
+fn ping_payload(epoch: Instant) -> Bytes {
+    Bytes::copy_from_slice(&(epoch.elapsed().as_micros() as u64).to_be_bytes())
+}
+
+fn round_trip(epoch: Instant, payload: &[u8]) -> Option<Duration> {
+    let sent = Duration::from_micros(u64::from_be_bytes(payload.try_into().ok()?));
+    epoch.elapsed().checked_sub(sent)
+}
+
+/// A server going down for a restart closes with 1012 and a reason like
+/// "server restarting, retry in 5s".
+fn restart_delay(frame: &CloseFrame) -> Option<Duration> {
+    if frame.code != CloseCode::Restart {
+        return None;
+    }
+
+    let hint = frame
+        .reason
+        .rsplit_once("retry in ")
+        .and_then(|(_, secs)| secs.strip_suffix('s')?.parse().ok())
+        .map(Duration::from_secs);
+
+    Some(hint.unwrap_or(RESTART_DELAY))
+}
+
+/// Trades the stored token for one with a new expiry and persists it.
+async fn refresh_token(app: &AppHandle) -> Result<String> {
+    let store = app.state::<AppState>();
+    let token = store
+        .get_access_token()
+        .await
+        .ok_or(Error::NotAuthenticated)?;
+
+    let response = reqwest::Client::new()
+        .post("http://localhost:8080/client/refresh")
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    let token = parse_access_token(response).await?;
+
+    store
+        .set_access_token(
+            token.clone(),
+            app.path().app_data_dir()?.join(constants::CONFIG_FILE_NAME),
+        )
+        .await?;
+
+    Ok(token)
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Answers AUTH_EXPIRING with a fresh token.
+async fn reauthenticate(app: &AppHandle, socket: &mut Socket) -> Result<()> {
+    let reauth = Event {
+        event_type: EventType::Reauth,
+        data: AuthPayload {
+            token: refresh_token(app).await?,
+        },
+    };
+
+    socket
+        .send(Message::text(serde_json::to_string(&reauth)?))
+        .await?;
+    Ok(())
+}
+
+/// Waits out `delay` before the next reconnect attempt, returning the delay
+/// for the attempt after it.
+async fn back_off(app: &AppHandle, delay: Duration) -> Duration {
+    emit(
+        app,
+        "webscoket-status",
+        json!({"status": "reconnecting", "retry_in_ms": delay.as_millis() as u64}),
+    );
+    tokio::time::sleep(delay).await;
+    (delay * 2).min(RECONNECT_MAX)
+}
+
+/// Renews the stored token, or registers, before opening the socket so a
+/// retry also recovers from a failed onboarding.
+async fn open(app: &AppHandle) -> Result<Socket> {
+    commands::auth::authenticate(app.clone()).await?;
+    connect(app).await
+}
+
+async fn connect(app: &AppHandle) -> Result<Socket> {
+    let token = app
+        .state::<AppState>()
+        .get_access_token()
+        .await
+        .ok_or(Error::NotAuthenticated)?;
+
+    let mut request = "ws://localhost:8080/ws".into_client_request()?;
+    request.headers_mut().insert(
+        "Authorization",
+        HeaderValue::from_str(&format!("Bearer {}", token)).map_err(|_| Error::Unauthorized)?,
+    );
 
-use crate::types::AppState;
+    let (socket, _) = connect_async(request).await?;
+    Ok(socket)
+}
+
+fn emit(app: &AppHandle, event: &str, payload: impl Serialize + Clone) {
+    if let Err(err) = app.emit(event, payload) {
+        eprintln!("Failed to emit {}: {}", event, err);
+    }
+}
+
+/// Forwards server events the frontend cares about.
+fn handle_event(app: &AppHandle, event: Event<serde_json::Value>) {
+    match event.event_type {
+        // Expired messages must not outlive the server copy, so they are
+        // purged from the local state as soon as the server reports them.
+        EventType::MessagesExpired => match serde_json::from_value::<MessagesExpired>(event.data) {
+            Ok(expired) => emit(app, "messages:expired", expired),
+            Err(err) => eprintln!("Invalid MESSAGES_EXPIRED payload: {}", err),
+        },
+        _ => {}
+    }
+}
 
 pub async fn start_ws_client(app: AppHandle, skip_frontend_wait: bool) {
     println!("Starting WebSocket client");
+    // Held for as long as this task runs, see `AppState::change_identity`.
+    let mut identity = app.state::<AppState>().watch_identity();
+
     let frontend_ready = Arc::new(Notify::new());
     let frontend_clone = frontend_ready.clone();
 
@@ -33,38 +190,183 @@ pub async fn start_ws_client(app: AppHandle, skip_frontend_wait: bool) {
     frontend_ready.notified().await;
     app.unlisten(id);
 
-    let store = app.state::<AppState>();
-    let mut url = "ws://localhost:8080/ws".into_client_request().unwrap();
-    let headers = url.headers_mut();
-    headers.insert(
-        "Authorization",
-        HeaderValue::from_str(store.get_access_token().await.unwrap().clone().as_str()).unwrap(),
-    );
+    let mut connected = false;
+    let mut backoff = RECONNECT_MIN;
+
+    loop {
+        let socket = match open(&app).await {
+            Ok(socket) => socket,
+            // Before the first connection, or once the server turned the
+            // client away, retrying is left to the frontend.
+            Err(err)
+                if !connected || matches!(err, Error::Unauthorized | Error::NotAuthenticated) =>
+            {
+                emit(
+                    &app,
+                    "initialization-status",
+                    json!({ "status": "failed", "error": err, "message": err.to_string() }),
+                );
+                eprintln!("Failed to connect to WebSocket server: {}", err);
+                return;
+            }
+            Err(err) => {
+                eprintln!("Failed to reconnect to WebSocket server: {}", err);
+                backoff = back_off(&app, backoff).await;
+                continue;
+            }
+        };
+
+        connected = true;
+        backoff = RECONNECT_MIN;
+
+        emit(
+            &app,
+            "initialization-status",
+            json!({ "status": "success" }),
+        );
 
-    let (mut socket, _) = match connect_async(url).await {
-        Ok(socket) => socket,
-        Err(err) => {
-            app.emit(
-                "initialization-status",
-                json!({ "status": "failed", "message": err.to_string() }),
-            )
-            .unwrap();
-            eprintln!("Failed to connect to WebSocket server: {}", err);
-            return;
+        let closed = run(&app, socket, &mut identity).await;
+        emit(&app, "webscoket-status", json!({"status": "disconnected"}));
+
+        match closed {
+            Closed::IdentityChanged => {}
+            Closed::Restarting(delay) => tokio::time::sleep(delay).await,
+            Closed::Lost => backoff = back_off(&app, backoff).await,
+            Closed::Stopped => break,
         }
-    };
+    }
+}
+
+/// Why `run` returned.
+enum Closed {
+    /// The socket was closed to reconnect as another identity.
+    IdentityChanged,
+    /// The server is restarting and asked to come back after this long.
+    Restarting(Duration),
+    /// The connection failed or the server stopped answering.
+    Lost,
+    /// The app is shutting down.
+    Stopped,
+}
+
+/// Handles the socket until it closes.
+async fn run(app: &AppHandle, mut socket: Socket, identity: &mut watch::Receiver<u64>) -> Closed {
+    let epoch = Instant::now();
+    let mut last_seen = Instant::now();
+    let mut quality: Option<ConnectionQuality> = None;
+    let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+
+    loop {
+        let msg = tokio::select! {
+            msg = socket.next() => match msg {
+                Some(Ok(msg)) => msg,
+                Some(Err(err)) => {
+                    eprintln!("WebSocket error: {}", err);
+                    return Closed::Lost;
+                }
+                None => return Closed::Lost,
+            },
+            _ = keepalive.tick() => {
+                if last_seen.elapsed() > SERVER_TIMEOUT {
+                    eprintln!("WebSocket server stopped responding");
+                    return Closed::Lost;
+                }
+
+                if let Err(err) = socket.send(Message::Ping(ping_payload(epoch))).await {
+                    eprintln!("Failed to send keepalive: {}", err);
+                    return Closed::Lost;
+                }
+                continue;
+            }
+            changed = identity.changed() => {
+                if let Err(err) = socket.close(None).await {
+                    eprintln!("Failed to close WebSocket: {}", err);
+                }
+                return match changed {
+                    Ok(()) => Closed::IdentityChanged,
+                    Err(_) => Closed::Stopped,
+                };
+            }
+        };
+        last_seen = Instant::now();
 
-    app.emit("initialization-status", json!({ "status": "success" }))
-        .unwrap();
-    while let Some(Ok(msg)) = socket.next().await {
         if let Message::Text(ref text) = msg {
-            println!("Received message: {}", text);
+            match serde_json::from_str::<Event<serde_json::Value>>(text) {
+                // Answered here rather than in `handle_event` since the reply
+                // goes out on this socket.
+                Ok(Event {
+                    event_type: EventType::AuthExpiring,
+                    ..
+                }) => {
+                    if let Err(err) = reauthenticate(app, &mut socket).await {
+                        eprintln!("Failed to reauthenticate: {}", err);
+                    }
+                }
+                // Kept to report along with our own measurement.
+                Ok(Event {
+                    event_type: EventType::ConnectionQuality,
+                    data,
+                }) => match serde_json::from_value(data) {
+                    Ok(data) => quality = Some(data),
+                    Err(err) => eprintln!("Invalid CONNECTION_QUALITY payload: {}", err),
+                },
+                Ok(event) => handle_event(app, event),
+                Err(err) => eprintln!("Failed to parse event: {}", err),
+            }
+        }
+
+        if let Message::Close(Some(ref frame)) = msg {
+            if let Some(delay) = restart_delay(frame) {
+                return Closed::Restarting(delay);
+            }
+        }
+
+        if let Message::Pong(ref payload) = msg {
+            if let Some(rtt) = round_trip(epoch, payload) {
+                emit(
+                    app,
+                    "webscoket-status",
+                    json!({
+                        "status": "alive",
+                        "latency_ms": rtt.as_secs_f64() * 1000.0,
+                        "smoothedRttMs": quality.map(|q| q.smoothed_rtt_ms),
+                        "jitterMs": quality.map(|q| q.jitter_ms),
+                    }),
+                );
+            }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if let Message::Ping(_) = msg {
-            app.emit("webscoket-status", json!({"status": "alive"}))
-                .unwrap();
-            socket.send(Message::Pong(Bytes::new())).await.unwrap();
+    fn frame(code: CloseCode, reason: &str) -> CloseFrame {
+        CloseFrame {
+            code,
+            reason: reason.into(),
         }
     }
+
+    #[test]
+    fn restart_waits_for_the_hint() {
+        let delay = restart_delay(&frame(
+            CloseCode::Restart,
+            "server restarting, retry in 12s",
+        ));
+        assert_eq!(delay, Some(Duration::from_secs(12)));
+    }
+
+    #[test]
+    fn restart_without_hint_waits_the_default() {
+        let delay = restart_delay(&frame(CloseCode::Restart, "server restarting"));
+        assert_eq!(delay, Some(RESTART_DELAY));
+    }
+
+    #[test]
+    fn other_closes_are_not_restarts() {
+        let delay = restart_delay(&frame(CloseCode::Normal, "retry in 12s"));
+        assert_eq!(delay, None);
+    }
 }
@@ -17,30 +17,58 @@ import { AnimatePresence, motion } from "motion/react";
 import { ChatUser } from "@/hooks/useAppManager";
 import { Avatar, AvatarFallback, AvatarImage } from "@radix-ui/react-avatar";
 import { ScrollArea } from "./ui/scroll-area";
-import { fetch } from "@tauri-apps/plugin-http";
+import { invoke } from "@tauri-apps/api/core";
+
+interface PublicUser {
+  id: string;
+  handle: string | null;
+  display_name: string | null;
+  bio: string | null;
+  avatar_url: string;
+  is_contact: boolean;
+  online?: boolean;
+}
+
+// Avatars require authentication, so they are loaded through the backend and
+// shown from an object url. An empty url falls back to the initials.
+async function fetchAvatar(id: string): Promise<string> {
+  try {
+    const bytes = await invoke<ArrayBuffer>("fetch_avatar", { id });
+    return URL.createObjectURL(new Blob([bytes], { type: "image/png" }));
+  } catch (error) {
+    console.error(error);
+    return "";
+  }
+}
 
 export default function ChatAddDialog() {
   const [isSearching, setIsSearching] = useState(false);
   const [isLoading, setIsLoading] = useState(false);
   const [searchResult, setSearchResult] = useState<ChatUser[] | null>(null);
+  const [query, setQuery] = useState("");
 
   const searchUsers = async (query: string) => {
     try {
       setIsSearching(true);
-      const response = await fetch(`http://localhost:8080/api/clients`);
-      const data: string[] = await response.json();
-      let user = data.map((id) => ({
-        id: id,
-        name: id,
-        avatar: `https://api.dicebear.com/7.x/initials/svg?seed=${id}`,
-      }));
+      setIsLoading(true);
+      const user = await invoke<PublicUser | null>("lookup_user", { query });
 
-      user.filter((user) => user.id == query);
-      // setSearchResult());
+      setSearchResult(
+        user
+          ? [
+              {
+                id: user.id,
+                username: user.display_name ?? user.handle ?? user.id,
+                avatar_url: await fetchAvatar(user.id),
+              },
+            ]
+          : [],
+      );
     } catch (error) {
       console.error(error);
     } finally {
       setIsSearching(false);
+      setIsLoading(false);
     }
   };
 
@@ -69,21 +97,16 @@ export default function ChatAddDialog() {
             >
               <Input
                 type="text"
-                placeholder="Enter friend's ID"
+                placeholder="Enter friend's ID or @handle"
                 disabled={isSearching}
+                value={query}
+                onChange={(e) => setQuery(e.target.value)}
               />
               <Button
                 type="submit"
                 variant="outline"
-                disabled={isSearching}
-                onClick={() => {
-                  setIsSearching(true);
-                  setIsLoading(true);
-                  setTimeout(() => {
-                    setIsSearching(false);
-                    setIsLoading(false);
-                  }, 4000);
-                }}
+                disabled={isSearching || query.trim() === ""}
+                onClick={() => searchUsers(query)}
               >
                 <UserSearch />
                 Search
@@ -133,9 +156,18 @@ export default function ChatAddDialog() {
                         <div className="flex-1">
                           <p className="text-sm font-medium">{user.username}</p>
                           <p className="text-xs text-muted-foreground">
-                            {user.username}
+                            {user.id}
                           </p>
                         </div>
+                        <Button
+                          size="sm"
+                          variant="outline"
+                          onClick={() =>
+                            invoke("send_contact_request", { id: user.id })
+                          }
+                        >
+                          Add
+                        </Button>
                       </motion.li>
                     ))}
                   </ul>
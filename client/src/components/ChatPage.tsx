@@ -14,6 +14,7 @@ import {
   useActiveConversation,
   useConversationActions,
   useConversationsList,
+  useMessageActions,
   useTypingActions,
   useUserActions,
 } from "@/hooks/MessagesProvider";
@@ -172,10 +173,22 @@ export function TypingIndicator({ typingUsers }: { typingUsers: any[] }) {
 
 export default function ChatPage() {
   const chatId = useAppManager((state) => state.chatId);
+  const { deleteMessage } = useMessageActions();
 
   useEffect(() => {
     listen("message:new", () => {});
     listen("user:typing", () => {});
+
+    const unlistenExpired = listen<{ ids: string[] }>(
+      "messages:expired",
+      (event) => {
+        event.payload.ids.forEach(deleteMessage);
+      },
+    );
+
+    return () => {
+      unlistenExpired.then((unlisten) => unlisten());
+    };
   }, []);
 
   return (
@@ -0,0 +1,19 @@
+-- Each script records itself here as its last statement, so readiness can
+-- tell which of them a database has been given.
+CREATE TABLE schema_migrations (
+    version INTEGER PRIMARY KEY,
+    applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE clients (
+    id VARCHAR(36) PRIMARY KEY
+);
+
+-- CREATE TABLE tokens (
+--     id VARCHAR(36) PRIMARY KEY NOT NULL,
+--     client_id VARCHAR(36) NOT NULL,
+--     token VARCHAR(255) NOT NULL,
+--     FOREIGN KEY (client_id) REFERENCES clients(id)
+-- );
+
+INSERT INTO schema_migrations (version) VALUES (0);
@@ -0,0 +1,23 @@
+ALTER TABLE clients ADD COLUMN contacts_only BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE TABLE contact_requests (
+    id VARCHAR(26) PRIMARY KEY,
+    sender_id VARCHAR(36) NOT NULL,
+    recipient_id VARCHAR(36) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (sender_id, recipient_id),
+    FOREIGN KEY (sender_id) REFERENCES clients(id) ON DELETE CASCADE,
+    FOREIGN KEY (recipient_id) REFERENCES clients(id) ON DELETE CASCADE
+);
+
+-- Contacts are stored once per direction so lookups only ever filter on client_id.
+CREATE TABLE contacts (
+    client_id VARCHAR(36) NOT NULL,
+    contact_id VARCHAR(36) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (client_id, contact_id),
+    FOREIGN KEY (client_id) REFERENCES clients(id) ON DELETE CASCADE,
+    FOREIGN KEY (contact_id) REFERENCES clients(id) ON DELETE CASCADE
+);
+
+INSERT INTO schema_migrations (version) VALUES (1);
@@ -0,0 +1,4 @@
+ALTER TABLE clients ADD COLUMN handle VARCHAR(32) UNIQUE;
+ALTER TABLE clients ADD COLUMN discoverable BOOLEAN NOT NULL DEFAULT TRUE;
+
+INSERT INTO schema_migrations (version) VALUES (2);
@@ -0,0 +1,20 @@
+CREATE TABLE blocks (
+    client_id VARCHAR(36) NOT NULL,
+    blocked_id VARCHAR(36) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (client_id, blocked_id),
+    FOREIGN KEY (client_id) REFERENCES clients(id) ON DELETE CASCADE,
+    FOREIGN KEY (blocked_id) REFERENCES clients(id) ON DELETE CASCADE
+);
+
+-- A conversation is identified by the peer on the other side of it.
+CREATE TABLE mutes (
+    client_id VARCHAR(36) NOT NULL,
+    peer_id VARCHAR(36) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (client_id, peer_id),
+    FOREIGN KEY (client_id) REFERENCES clients(id) ON DELETE CASCADE,
+    FOREIGN KEY (peer_id) REFERENCES clients(id) ON DELETE CASCADE
+);
+
+INSERT INTO schema_migrations (version) VALUES (3);
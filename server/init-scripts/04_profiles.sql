@@ -0,0 +1,10 @@
+CREATE TABLE profiles (
+    client_id VARCHAR(36) PRIMARY KEY,
+    display_name VARCHAR(64),
+    bio VARCHAR(280),
+    avatar_url TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (client_id) REFERENCES clients(id) ON DELETE CASCADE
+);
+
+INSERT INTO schema_migrations (version) VALUES (4);
@@ -0,0 +1,5 @@
+-- Avatars are hosted by the server now, the profile only records which upload is current.
+ALTER TABLE profiles DROP COLUMN avatar_url;
+ALTER TABLE profiles ADD COLUMN avatar_version VARCHAR(26);
+
+INSERT INTO schema_migrations (version) VALUES (5);
@@ -0,0 +1,15 @@
+CREATE TABLE messages (
+    id VARCHAR(26) PRIMARY KEY,
+    sender_id VARCHAR(36) NOT NULL,
+    recipient_id VARCHAR(36) NOT NULL,
+    content TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (sender_id) REFERENCES clients(id) ON DELETE CASCADE,
+    FOREIGN KEY (recipient_id) REFERENCES clients(id) ON DELETE CASCADE
+);
+
+-- History is always read per conversation, regardless of who sent what.
+CREATE INDEX messages_conversation_idx
+    ON messages (LEAST(sender_id, recipient_id), GREATEST(sender_id, recipient_id), id);
+
+INSERT INTO schema_migrations (version) VALUES (6);
@@ -0,0 +1,19 @@
+CREATE TABLE attachments (
+    id VARCHAR(26) PRIMARY KEY,
+    uploader_id VARCHAR(36) NOT NULL,
+    message_id VARCHAR(26),
+    file_name VARCHAR(255) NOT NULL,
+    content_type VARCHAR(255) NOT NULL,
+    size BIGINT NOT NULL,
+    chunk_size INTEGER NOT NULL,
+    sha256 CHAR(64) NOT NULL,
+    completed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (uploader_id) REFERENCES clients(id) ON DELETE CASCADE,
+    FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
+
+CREATE INDEX attachments_message_idx ON attachments (message_id);
+CREATE INDEX attachments_sha256_idx ON attachments (sha256);
+
+INSERT INTO schema_migrations (version) VALUES (7);
@@ -0,0 +1,8 @@
+ALTER TABLE attachments
+    ADD COLUMN width INTEGER,
+    ADD COLUMN height INTEGER,
+    ADD COLUMN blurhash VARCHAR(64),
+    ADD COLUMN has_thumbnail BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN processed_at TIMESTAMPTZ;
+
+INSERT INTO schema_migrations (version) VALUES (8);
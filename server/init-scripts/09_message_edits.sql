@@ -0,0 +1,16 @@
+ALTER TABLE messages
+    ADD COLUMN edited_at TIMESTAMPTZ,
+    ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- Previous versions of edited messages, oldest first per message.
+CREATE TABLE message_edits (
+    id SERIAL PRIMARY KEY,
+    message_id VARCHAR(26) NOT NULL,
+    content TEXT NOT NULL,
+    replaced_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
+
+CREATE INDEX message_edits_message_idx ON message_edits (message_id, id);
+
+INSERT INTO schema_migrations (version) VALUES (9);
@@ -0,0 +1,17 @@
+-- Custom emoji are referenced by shortcode, e.g. ":party_parrot:".
+CREATE TABLE custom_emojis (
+    id VARCHAR(64) PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE reactions (
+    message_id VARCHAR(26) NOT NULL,
+    client_id VARCHAR(36) NOT NULL,
+    emoji VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (message_id, client_id, emoji),
+    FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE,
+    FOREIGN KEY (client_id) REFERENCES clients(id) ON DELETE CASCADE
+);
+
+INSERT INTO schema_migrations (version) VALUES (10);
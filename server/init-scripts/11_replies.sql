@@ -0,0 +1,10 @@
+ALTER TABLE messages
+    ADD COLUMN parent_id VARCHAR(26) REFERENCES messages(id) ON DELETE SET NULL,
+    -- The first message of the thread, shared by every reply in it.
+    ADD COLUMN root_id VARCHAR(26) REFERENCES messages(id) ON DELETE SET NULL,
+    ADD COLUMN reply_count INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN last_reply_at TIMESTAMPTZ;
+
+CREATE INDEX messages_thread_idx ON messages (root_id, id);
+
+INSERT INTO schema_migrations (version) VALUES (11);
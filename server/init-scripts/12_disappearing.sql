@@ -0,0 +1,19 @@
+-- Settings shared by both sides of a conversation, keyed by the ordered pair
+-- of participants.
+CREATE TABLE conversation_settings (
+    low_id VARCHAR(36) NOT NULL,
+    high_id VARCHAR(36) NOT NULL,
+    -- Messages sent while this is set expire this many seconds after sending.
+    disappear_after INTEGER,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (low_id, high_id),
+    CHECK (low_id < high_id),
+    FOREIGN KEY (low_id) REFERENCES clients(id) ON DELETE CASCADE,
+    FOREIGN KEY (high_id) REFERENCES clients(id) ON DELETE CASCADE
+);
+
+ALTER TABLE messages ADD COLUMN expires_at TIMESTAMPTZ;
+
+CREATE INDEX messages_expires_idx ON messages (expires_at) WHERE expires_at IS NOT NULL;
+
+INSERT INTO schema_migrations (version) VALUES (12);
@@ -0,0 +1,8 @@
+-- The 'simple' configuration does no stemming, which keeps search usable for
+-- every language people chat in.
+ALTER TABLE messages
+    ADD COLUMN search tsvector GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED;
+
+CREATE INDEX messages_search_idx ON messages USING GIN (search);
+
+INSERT INTO schema_migrations (version) VALUES (13);
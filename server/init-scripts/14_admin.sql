@@ -0,0 +1,19 @@
+-- Granted by operators directly in the database, e.g. '{admin}'.
+ALTER TABLE clients ADD COLUMN roles TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE clients ADD COLUMN banned_at TIMESTAMPTZ;
+ALTER TABLE clients ADD COLUMN ban_reason TEXT;
+-- Tokens issued in a second before this one are rejected. Token issue times
+-- only have second precision, so tokens issued within the same second pass.
+ALTER TABLE clients ADD COLUMN tokens_valid_after TIMESTAMPTZ;
+
+CREATE TABLE admin_audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    -- The admin's client id, or "admin-key" for requests made with the key.
+    actor VARCHAR(36) NOT NULL,
+    action VARCHAR(32) NOT NULL,
+    target VARCHAR(36),
+    details JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO schema_migrations (version) VALUES (14);
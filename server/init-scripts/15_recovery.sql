@@ -0,0 +1,5 @@
+-- SHA-256 of the client's recovery key, hex encoded. The key itself is only
+-- ever shown to the client.
+ALTER TABLE clients ADD COLUMN recovery_hash CHAR(64) UNIQUE;
+
+INSERT INTO schema_migrations (version) VALUES (15);
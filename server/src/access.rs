@@ -0,0 +1,159 @@
+//! Bans and token revocations. They live in the `clients` table and are
+//! mirrored here so every request can be checked without a database round
+//! trip.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+use uuid::Uuid;
+
+use crate::{
+    error::{Error, Result},
+    types::Claims,
+};
+
+#[derive(Default)]
+struct Restrictions {
+    banned: HashSet<Uuid>,
+    /// Tokens for the client issued before this Unix timestamp are no longer
+    /// valid. Issue times only have second precision, so the revocation time
+    /// is floored and tokens issued in the same second stay valid. Otherwise
+    /// a token issued right after a revocation would be rejected too.
+    tokens_valid_after: HashMap<Uuid, i64>,
+}
+
+#[derive(Default)]
+pub struct AccessList {
+    restrictions: RwLock<Restrictions>,
+}
+
+impl AccessList {
+    pub async fn load(pool: &sqlx::PgPool) -> Result<Self> {
+        let rows = sqlx::query!(
+            "SELECT id, banned_at IS NOT NULL AS \"banned!\",
+                FLOOR(EXTRACT(EPOCH FROM tokens_valid_after))::BIGINT AS tokens_valid_after
+            FROM clients
+            WHERE banned_at IS NOT NULL OR tokens_valid_after IS NOT NULL"
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut restrictions = Restrictions::default();
+
+        for row in rows {
+            let id = Uuid::parse_str(&row.id)?;
+
+            if row.banned {
+                restrictions.banned.insert(id);
+            }
+            if let Some(valid_after) = row.tokens_valid_after {
+                restrictions.tokens_valid_after.insert(id, valid_after);
+            }
+        }
+
+        Ok(Self {
+            restrictions: RwLock::new(restrictions),
+        })
+    }
+
+    /// Rejects tokens of banned clients and tokens issued before a revocation.
+    pub fn check(&self, claims: &Claims) -> Result<()> {
+        let restrictions = self.restrictions.read().unwrap_or_else(|e| e.into_inner());
+
+        if restrictions.banned.contains(&claims.id) {
+            return Err(Error::Forbidden);
+        }
+
+        match restrictions.tokens_valid_after.get(&claims.id) {
+            Some(valid_after) if (claims.iat as i64) < *valid_after => Err(Error::Unauthorized),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn set_banned(&self, id: Uuid, banned: bool) {
+        let mut restrictions = self.restrictions.write().unwrap_or_else(|e| e.into_inner());
+
+        match banned {
+            true => restrictions.banned.insert(id),
+            false => restrictions.banned.remove(&id),
+        };
+    }
+
+    pub fn revoke_tokens(&self, id: Uuid, valid_after: i64) {
+        self.restrictions
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .tokens_valid_after
+            .insert(id, valid_after);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(id: Uuid, iat: usize) -> Claims {
+        Claims {
+            id,
+            exp: iat + 3600,
+            iat,
+            roles: Vec::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn unrestricted_clients_pass() {
+        let access = AccessList::default();
+
+        assert!(access.check(&claims(Uuid::new_v4(), 1_000)).is_ok());
+    }
+
+    #[test]
+    fn banned_clients_are_forbidden_until_unbanned() {
+        let access = AccessList::default();
+        let id = Uuid::new_v4();
+
+        access.set_banned(id, true);
+        assert!(matches!(
+            access.check(&claims(id, 1_000)),
+            Err(Error::Forbidden)
+        ));
+        assert!(access.check(&claims(Uuid::new_v4(), 1_000)).is_ok());
+
+        access.set_banned(id, false);
+        assert!(access.check(&claims(id, 1_000)).is_ok());
+    }
+
+    #[test]
+    fn revocation_rejects_tokens_issued_before_it() {
+        let access = AccessList::default();
+        let id = Uuid::new_v4();
+
+        access.revoke_tokens(id, 1_000);
+
+        assert!(matches!(
+            access.check(&claims(id, 999)),
+            Err(Error::Unauthorized)
+        ));
+        assert!(access.check(&claims(id, 1_000)).is_ok());
+        assert!(access.check(&claims(id, 1_001)).is_ok());
+        assert!(access.check(&claims(Uuid::new_v4(), 999)).is_ok());
+    }
+
+    #[test]
+    fn bans_take_precedence_over_revocations() {
+        let access = AccessList::default();
+        let id = Uuid::new_v4();
+
+        access.revoke_tokens(id, 1_000);
+        access.set_banned(id, true);
+
+        assert!(matches!(
+            access.check(&claims(id, 999)),
+            Err(Error::Forbidden)
+        ));
+    }
+}
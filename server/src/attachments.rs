@@ -0,0 +1,140 @@
+//! On-disk layout for attachments. Chunks are staged per upload until the
+//! upload is finalized, after which the content lives in a blob store keyed
+//! by its SHA-256 so identical files are only stored once.
+
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::{error::Result, types::Appstate};
+
+pub fn staging_dir(app_state: &Appstate, id: &str) -> PathBuf {
+    app_state.data_dir().join("uploads").join(id)
+}
+
+pub fn chunk_path(app_state: &Appstate, id: &str, index: u32) -> PathBuf {
+    staging_dir(app_state, id).join(format!("{index}.part"))
+}
+
+pub fn blob_path(app_state: &Appstate, sha256: &str) -> PathBuf {
+    app_state
+        .data_dir()
+        .join("blobs")
+        .join(&sha256[..2])
+        .join(sha256)
+}
+
+pub fn thumbnail_path(app_state: &Appstate, sha256: &str) -> PathBuf {
+    app_state
+        .data_dir()
+        .join("thumbnails")
+        .join(&sha256[..2])
+        .join(format!("{sha256}.jpg"))
+}
+
+/// Indices of the chunks of an upload that have already been received.
+pub async fn received_chunks(app_state: &Appstate, id: &str) -> Result<Vec<u32>> {
+    let mut received = Vec::new();
+
+    let mut entries = match tokio::fs::read_dir(staging_dir(app_state, id)).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(received),
+        Err(e) => return Err(e.into()),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(index) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_suffix(".part"))
+            .and_then(|index| index.parse().ok())
+        {
+            received.push(index);
+        }
+    }
+
+    received.sort_unstable();
+    Ok(received)
+}
+
+/// Concatenates the staged chunks into a single file next to the blob store,
+/// returning its path, size and SHA-256. Each call writes its own file, so
+/// concurrent finalizes of one upload cannot interleave.
+pub async fn assemble(
+    app_state: &Appstate,
+    id: &str,
+    chunks: u32,
+) -> Result<(PathBuf, u64, String)> {
+    let tmp_dir = app_state.data_dir().join("blobs").join("tmp");
+    tokio::fs::create_dir_all(&tmp_dir).await?;
+
+    let tmp = tmp_dir.join(format!("{id}.{}", Uuid::new_v4()));
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+
+    for index in 0..chunks {
+        let chunk = tokio::fs::read(chunk_path(app_state, id, index)).await?;
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        size += chunk.len() as u64;
+    }
+
+    file.flush().await?;
+
+    Ok((tmp, size, format!("{:x}", hasher.finalize())))
+}
+
+/// Moves an assembled file into the blob store, dropping it if identical
+/// content is stored already.
+pub async fn store_blob(app_state: &Appstate, tmp: &Path, sha256: &str) -> Result<()> {
+    let path = blob_path(app_state, sha256);
+
+    if tokio::fs::try_exists(&path).await? {
+        tokio::fs::remove_file(tmp).await?;
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    tokio::fs::rename(tmp, path).await?;
+    Ok(())
+}
+
+pub async fn discard_staging(app_state: &Appstate, id: &str) -> Result<()> {
+    match tokio::fs::remove_dir_all(staging_dir(app_state, id)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Deletes the stored content and preview of each hash that no attachment
+/// refers to anymore. Pending uploads count as references, since they will
+/// land on the same blob when finalized.
+pub async fn remove_unreferenced(app_state: &Appstate, hashes: &[String]) -> Result<()> {
+    let unreferenced = sqlx::query_scalar!(
+        "SELECT h.sha256 AS \"sha256!\" FROM UNNEST($1::text[]) AS h(sha256)
+        WHERE NOT EXISTS (SELECT 1 FROM attachments a WHERE a.sha256 = h.sha256)",
+        hashes
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    for sha256 in unreferenced {
+        for path in [
+            blob_path(app_state, &sha256),
+            thumbnail_path(app_state, &sha256),
+        ] {
+            match tokio::fs::remove_file(path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
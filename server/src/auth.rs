@@ -0,0 +1,213 @@
+//! Request authentication. Handlers take an [`Authenticated`] argument that
+//! names the scope they need, e.g. `Authenticated<scopes::Messages>`, and get
+//! the caller's validated claims.
+//!
+//! Tokens are sent as `Authorization: Bearer <token>`. A bare token is still
+//! accepted for clients that predate the scheme.
+
+use std::{
+    future::{Ready, ready},
+    marker::PhantomData,
+    sync::Arc,
+};
+
+use actix_web::{FromRequest, HttpRequest, dev::Payload, web};
+use uuid::Uuid;
+
+use crate::{
+    error::{Error, Result},
+    metrics::METRICS,
+    types::{Appstate, Claims, Scope},
+};
+
+/// A scope a handler requires, see [`scopes`].
+pub trait RequiredScope {
+    const SCOPE: Scope;
+}
+
+pub mod scopes {
+    use super::RequiredScope;
+    use crate::types::Scope;
+
+    pub struct Messages;
+    pub struct Contacts;
+    pub struct Profile;
+    pub struct Admin;
+
+    impl RequiredScope for Messages {
+        const SCOPE: Scope = Scope::Messages;
+    }
+
+    impl RequiredScope for Contacts {
+        const SCOPE: Scope = Scope::Contacts;
+    }
+
+    impl RequiredScope for Profile {
+        const SCOPE: Scope = Scope::Profile;
+    }
+
+    impl RequiredScope for Admin {
+        const SCOPE: Scope = Scope::Admin;
+    }
+}
+
+/// A request carrying a valid, unrevoked token of a client that is not
+/// banned, with the scope `S`.
+pub struct Authenticated<S> {
+    pub claims: Claims,
+    scope: PhantomData<S>,
+}
+
+impl<S> Authenticated<S> {
+    pub fn id(&self) -> Uuid {
+        self.claims.id
+    }
+}
+
+/// Subprotocol a browser offers alongside its token, since it cannot set
+/// headers on a websocket handshake: `Sec-WebSocket-Protocol: bearer, <token>`.
+pub const BEARER_PROTOCOL: &str = "bearer";
+
+/// The token in the `Authorization` header, if there is one.
+pub fn header_token(req: &HttpRequest) -> Option<&str> {
+    let value = req.headers().get("Authorization")?.to_str().ok()?.trim();
+
+    match value.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => Some(token.trim()),
+        Some(_) => None,
+        None => Some(value),
+    }
+}
+
+/// The token offered after [`BEARER_PROTOCOL`] in `Sec-WebSocket-Protocol`.
+pub fn subprotocol_token(req: &HttpRequest) -> Option<&str> {
+    let mut protocols = req
+        .headers()
+        .get(actix_web::http::header::SEC_WEBSOCKET_PROTOCOL)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .map(str::trim);
+
+    protocols.find(|protocol| *protocol == BEARER_PROTOCOL)?;
+    protocols.next().filter(|token| !token.is_empty())
+}
+
+/// Validates a token and checks that it was not revoked and that its client
+/// is not banned.
+pub fn validate(app_state: &Appstate, token: &str) -> Result<Claims> {
+    let claims = app_state.token_manager.validate_token(token)?;
+    app_state.access.check(&claims)?;
+
+    Ok(claims)
+}
+
+/// Validates the request's token, see [`validate`].
+pub fn claims(req: &HttpRequest, app_state: &Appstate) -> Result<Claims> {
+    validate(app_state, header_token(req).ok_or(Error::Unauthorized)?)
+}
+
+impl<S: RequiredScope> Authenticated<S> {
+    fn with_scope(claims: Result<Claims>) -> Result<Self> {
+        claims
+            .and_then(|claims| match claims.scopes.contains(&S::SCOPE) {
+                true => Ok(Self {
+                    claims,
+                    scope: PhantomData,
+                }),
+                false => Err(Error::Forbidden),
+            })
+            .inspect_err(|e| METRICS.auth_failure(e))
+    }
+
+    pub fn extract(req: &HttpRequest) -> Result<Self> {
+        let Some(app_state) = req.app_data::<web::Data<Arc<Appstate>>>() else {
+            return Err(actix_web::error::ErrorInternalServerError("App state is missing").into());
+        };
+
+        Self::with_scope(claims(req, app_state))
+    }
+
+    /// Authenticates a token that did not come in the `Authorization`
+    /// header, e.g. one sent over a websocket.
+    pub fn from_token(app_state: &Appstate, token: &str) -> Result<Self> {
+        Self::with_scope(validate(app_state, token))
+    }
+}
+
+impl<S: RequiredScope> FromRequest for Authenticated<S> {
+    type Error = Error;
+    type Future = Ready<Result<Self>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Self::extract(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::header, test::TestRequest};
+
+    use super::*;
+
+    fn with_authorization(value: &str) -> HttpRequest {
+        TestRequest::default()
+            .insert_header((header::AUTHORIZATION, value))
+            .to_http_request()
+    }
+
+    fn with_protocols(value: &str) -> HttpRequest {
+        TestRequest::default()
+            .insert_header((header::SEC_WEBSOCKET_PROTOCOL, value))
+            .to_http_request()
+    }
+
+    #[test]
+    fn header_token_accepts_bearer_in_any_case() {
+        for value in ["Bearer abc", "bearer abc", "BEARER abc", "  Bearer   abc  "] {
+            assert_eq!(
+                header_token(&with_authorization(value)),
+                Some("abc"),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn header_token_accepts_bare_tokens() {
+        assert_eq!(header_token(&with_authorization("abc")), Some("abc"));
+    }
+
+    #[test]
+    fn header_token_rejects_other_schemes() {
+        assert_eq!(header_token(&with_authorization("Basic abc")), None);
+        assert_eq!(
+            header_token(&TestRequest::default().to_http_request()),
+            None
+        );
+    }
+
+    #[test]
+    fn subprotocol_token_follows_bearer() {
+        assert_eq!(
+            subprotocol_token(&with_protocols("bearer, abc")),
+            Some("abc")
+        );
+        assert_eq!(
+            subprotocol_token(&with_protocols("chat, bearer,abc")),
+            Some("abc")
+        );
+    }
+
+    #[test]
+    fn subprotocol_token_requires_bearer_and_a_token() {
+        assert_eq!(subprotocol_token(&with_protocols("abc")), None);
+        assert_eq!(subprotocol_token(&with_protocols("chat, abc")), None);
+        assert_eq!(subprotocol_token(&with_protocols("bearer")), None);
+        assert_eq!(subprotocol_token(&with_protocols("bearer, ")), None);
+        assert_eq!(
+            subprotocol_token(&TestRequest::default().to_http_request()),
+            None
+        );
+    }
+}
@@ -0,0 +1,334 @@
+//! Operator endpoints. They take either the admin key in `X-Admin-Key` or a
+//! token with the admin scope, and every change they make is recorded in
+//! the audit log.
+
+use std::{
+    future::{Ready, ready},
+    sync::Arc,
+};
+
+use actix::Addr;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, dev::Payload, web};
+use actix_web_actors::ws::CloseCode;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Authenticated, scopes},
+    error::{Error, Result},
+    metrics::METRICS,
+    types::Appstate,
+    websocket::{
+        actions::{Kick, ListSessions},
+        server::Server,
+    },
+};
+
+const ADMIN_KEY_HEADER: &str = "X-Admin-Key";
+/// Recorded as the actor for requests made with the admin key.
+const ADMIN_KEY_ACTOR: &str = "admin-key";
+const DEFAULT_AUDIT_LIMIT: i64 = 50;
+const MAX_AUDIT_LIMIT: i64 = 200;
+
+#[derive(Deserialize)]
+pub struct BanPayload {
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// The caller of an admin endpoint, either holding the admin key or a token
+/// with the admin scope.
+pub struct Admin {
+    /// Who is acting, for the audit log.
+    actor: String,
+}
+
+impl Admin {
+    fn extract(req: &HttpRequest) -> Result<Self> {
+        let Some(key) = req.headers().get(ADMIN_KEY_HEADER) else {
+            return Ok(Admin {
+                actor: Authenticated::<scopes::Admin>::extract(req)?
+                    .id()
+                    .to_string(),
+            });
+        };
+
+        // Comparing digests keeps the comparison from leaking how much of
+        // the key matched.
+        let matches = req
+            .app_data::<web::Data<Arc<Appstate>>>()
+            .and_then(|app_state| app_state.admin_key.as_ref())
+            .is_some_and(|expected| {
+                Sha256::digest(key.as_bytes()) == Sha256::digest(expected.as_bytes())
+            });
+
+        if !matches {
+            METRICS.auth_failure(&Error::Unauthorized);
+            return Err(Error::Unauthorized);
+        }
+
+        Ok(Admin {
+            actor: ADMIN_KEY_ACTOR.into(),
+        })
+    }
+}
+
+impl FromRequest for Admin {
+    type Error = Error;
+    type Future = Ready<Result<Self>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Self::extract(req))
+    }
+}
+
+/// Records an admin action. Callers write it in the transaction that applies
+/// the action, so neither happens without the other.
+async fn audit(
+    conn: &mut sqlx::PgConnection,
+    actor: &str,
+    action: &str,
+    target: Uuid,
+    details: serde_json::Value,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO admin_audit_log (actor, action, target, details) VALUES ($1, $2, $3, $4)",
+        actor,
+        action,
+        target.to_string(),
+        details
+    )
+    .execute(conn)
+    .await?;
+
+    tracing::info!(%actor, %action, %target, "Admin action");
+    Ok(())
+}
+
+#[actix_web::get("/api/admin/sessions")]
+pub async fn list_sessions(_admin: Admin, srv: web::Data<Addr<Server>>) -> Result<HttpResponse> {
+    let mut sessions = srv.send(ListSessions).await?;
+    sessions.sort_by_key(|session| session.connected_at);
+
+    Ok(HttpResponse::Ok().json(json!({"sessions": sessions})))
+}
+
+#[actix_web::delete("/api/admin/sessions/{id}")]
+pub async fn kick(
+    admin: Admin,
+    id: web::Path<Uuid>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = id.into_inner();
+
+    // A kick has nothing to persist, the entry is written first and only
+    // kept if there was a session to close.
+    let mut tx = app_state.pool().begin().await?;
+    audit(&mut tx, &admin.actor, "kick", id, json!({})).await?;
+
+    let kicked = srv
+        .send(Kick {
+            id,
+            code: CloseCode::Policy,
+            reason: "kicked".into(),
+        })
+        .await?;
+
+    if !kicked {
+        return Err(Error::NotFound);
+    }
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[actix_web::put("/api/admin/bans/{id}")]
+pub async fn ban(
+    admin: Admin,
+    id: web::Path<Uuid>,
+    payload: Option<web::Json<BanPayload>>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = id.into_inner();
+    let reason = payload.and_then(|payload| payload.into_inner().reason);
+
+    let mut tx = app_state.pool().begin().await?;
+
+    let updated = sqlx::query!(
+        "UPDATE clients SET banned_at = COALESCE(banned_at, NOW()), ban_reason = $2 WHERE id = $1",
+        id.to_string(),
+        reason
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    if updated == 0 {
+        return Err(Error::NotFound);
+    }
+
+    audit(&mut tx, &admin.actor, "ban", id, json!({"reason": reason})).await?;
+    tx.commit().await?;
+
+    app_state.access.set_banned(id, true);
+    srv.send(Kick {
+        id,
+        code: CloseCode::Policy,
+        reason: "banned".into(),
+    })
+    .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[actix_web::delete("/api/admin/bans/{id}")]
+pub async fn unban(
+    admin: Admin,
+    id: web::Path<Uuid>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = id.into_inner();
+
+    let mut tx = app_state.pool().begin().await?;
+
+    let updated = sqlx::query!(
+        "UPDATE clients SET banned_at = NULL, ban_reason = NULL WHERE id = $1",
+        id.to_string()
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    if updated == 0 {
+        return Err(Error::NotFound);
+    }
+
+    audit(&mut tx, &admin.actor, "unban", id, json!({})).await?;
+    tx.commit().await?;
+
+    app_state.access.set_banned(id, false);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Invalidates the tokens issued to the client so far and closes its
+/// session. Tokens record their issue time in whole seconds, so a token
+/// issued less than a second before the revocation may survive it.
+#[actix_web::post("/api/admin/clients/{id}/revoke-tokens")]
+pub async fn revoke_tokens(
+    admin: Admin,
+    id: web::Path<Uuid>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = id.into_inner();
+    let mut tx = app_state.pool().begin().await?;
+
+    let valid_after = sqlx::query_scalar!(
+        "UPDATE clients SET tokens_valid_after = NOW() WHERE id = $1
+        RETURNING FLOOR(EXTRACT(EPOCH FROM tokens_valid_after))::BIGINT AS \"valid_after!\"",
+        id.to_string()
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    audit(&mut tx, &admin.actor, "revoke_tokens", id, json!({})).await?;
+    tx.commit().await?;
+
+    app_state.access.revoke_tokens(id, valid_after);
+    srv.send(Kick {
+        id,
+        code: CloseCode::Policy,
+        reason: "token revoked".into(),
+    })
+    .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[actix_web::get("/api/admin/stats")]
+pub async fn stats(
+    _admin: Admin,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let counts = sqlx::query!(
+        "SELECT
+            (SELECT COUNT(*) FROM clients) AS \"clients!\",
+            (SELECT COUNT(*) FROM clients WHERE banned_at IS NOT NULL) AS \"banned!\",
+            (SELECT COUNT(*) FROM messages) AS \"messages!\",
+            (SELECT COUNT(*) FROM messages WHERE created_at > NOW() - INTERVAL '1 day') AS \"messages_last_day!\",
+            (SELECT COUNT(*) FROM attachments WHERE completed_at IS NOT NULL) AS \"attachments!\",
+            (SELECT COALESCE(SUM(size), 0)::BIGINT FROM attachments WHERE completed_at IS NOT NULL) AS \"attachment_bytes!\""
+    )
+    .fetch_one(app_state.pool())
+    .await?;
+
+    let sessions = srv.send(ListSessions).await?.len();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "sessions": sessions,
+        "clients": counts.clients,
+        "banned": counts.banned,
+        "messages": counts.messages,
+        "messages_last_day": counts.messages_last_day,
+        "attachments": counts.attachments,
+        "attachment_bytes": counts.attachment_bytes,
+    })))
+}
+
+/// Newest entries first. Pass the last id as `before` for the next page.
+#[actix_web::get("/api/admin/audit")]
+pub async fn audit_log(
+    _admin: Admin,
+    query: web::Query<AuditQuery>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .clamp(1, MAX_AUDIT_LIMIT);
+
+    let entries = sqlx::query!(
+        "SELECT id, actor, action, target, details, created_at FROM admin_audit_log
+        WHERE $1::BIGINT IS NULL OR id < $1
+        ORDER BY id DESC
+        LIMIT $2",
+        query.before,
+        limit
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    let next_before = match entries.len() as i64 == limit {
+        true => entries.last().map(|entry| entry.id),
+        false => None,
+    };
+
+    let entries = entries
+        .into_iter()
+        .map(|entry| {
+            json!({
+                "id": entry.id,
+                "actor": entry.actor,
+                "action": entry.action,
+                "target": entry.target,
+                "details": entry.details,
+                "created_at": entry.created_at,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(json!({"entries": entries, "next_before": next_before})))
+}
@@ -0,0 +1,421 @@
+//! Attachments are uploaded in fixed-size chunks before the message that
+//! carries them is sent, so an interrupted upload can resume by asking which
+//! chunks the server already has.
+
+use std::sync::Arc;
+
+use actix::Addr;
+use actix_files::NamedFile;
+use actix_web::{
+    HttpRequest, HttpResponse, Responder,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    mime, web,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    attachments,
+    auth::{Authenticated, scopes},
+    error::{Error, Result},
+    messages, thumbnails,
+    types::Appstate,
+    websocket::server::Server,
+};
+
+const MAX_FILE_SIZE: i64 = 100 * 1024 * 1024;
+const MIN_CHUNK_SIZE: i32 = 64 * 1024;
+const MAX_CHUNK_SIZE: i32 = 8 * 1024 * 1024;
+const DEFAULT_CHUNK_SIZE: i32 = 1024 * 1024;
+/// Limits on what a client may have uploaded but not sent yet. Anything left
+/// unsent is dropped by the reaper after a day.
+const MAX_UNSENT_UPLOADS: i64 = 20;
+const MAX_UNSENT_SIZE: i64 = 1024 * 1024 * 1024;
+
+#[derive(Deserialize)]
+pub struct CreateUploadPayload {
+    pub file_name: String,
+    pub content_type: String,
+    pub size: i64,
+    /// Hex encoded SHA-256 of the whole file, checked when finalizing.
+    pub sha256: String,
+    pub chunk_size: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct Attachment {
+    pub id: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: i64,
+    pub chunk_size: i32,
+    pub chunk_count: u32,
+    pub sha256: String,
+    pub message_id: Option<String>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub blurhash: Option<String>,
+    pub has_thumbnail: bool,
+    /// Only present while the upload is still in progress.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_chunks: Option<Vec<u32>>,
+}
+
+struct AttachmentRow {
+    id: String,
+    uploader_id: String,
+    message_id: Option<String>,
+    file_name: String,
+    content_type: String,
+    size: i64,
+    chunk_size: i32,
+    sha256: String,
+    completed_at: Option<DateTime<Utc>>,
+    width: Option<i32>,
+    height: Option<i32>,
+    blurhash: Option<String>,
+    has_thumbnail: bool,
+}
+
+impl AttachmentRow {
+    fn chunk_count(&self) -> u32 {
+        (self.size as u64).div_ceil(self.chunk_size as u64).max(1) as u32
+    }
+
+    /// Expected length of chunk `index`; only the last one may be short.
+    fn chunk_len(&self, index: u32) -> usize {
+        let start = index as i64 * self.chunk_size as i64;
+        (self.size - start).min(self.chunk_size as i64) as usize
+    }
+
+    fn into_attachment(self, received_chunks: Option<Vec<u32>>) -> Attachment {
+        Attachment {
+            chunk_count: self.chunk_count(),
+            id: self.id,
+            file_name: self.file_name,
+            content_type: self.content_type,
+            size: self.size,
+            chunk_size: self.chunk_size,
+            sha256: self.sha256,
+            message_id: self.message_id,
+            completed_at: self.completed_at,
+            width: self.width,
+            height: self.height,
+            blurhash: self.blurhash,
+            has_thumbnail: self.has_thumbnail,
+            received_chunks,
+        }
+    }
+}
+
+async fn find(app_state: &Appstate, id: &str) -> Result<AttachmentRow> {
+    sqlx::query_as!(
+        AttachmentRow,
+        "SELECT id, uploader_id, message_id, file_name, content_type, size, chunk_size, sha256, completed_at, width, height, blurhash, has_thumbnail
+        FROM attachments WHERE id = $1",
+        id
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// An upload that `caller` started and may still add chunks to.
+async fn find_pending(app_state: &Appstate, caller: Uuid, id: &str) -> Result<AttachmentRow> {
+    let row = find(app_state, id).await?;
+
+    if row.uploader_id != caller.to_string() {
+        return Err(Error::NotFound);
+    }
+
+    if row.completed_at.is_some() {
+        return Err(Error::Conflict("Upload is already finalized".into()));
+    }
+
+    Ok(row)
+}
+
+/// Attachments are visible to their uploader and, once sent, to both
+/// participants of the message.
+async fn find_visible(app_state: &Appstate, caller: Uuid, id: &str) -> Result<AttachmentRow> {
+    let row = find(app_state, id).await?;
+
+    let visible = row.uploader_id == caller.to_string()
+        || match &row.message_id {
+            Some(message_id) => messages::is_participant(app_state, caller, message_id).await?,
+            None => false,
+        };
+
+    match visible {
+        true => Ok(row),
+        false => Err(Error::NotFound),
+    }
+}
+
+#[actix_web::post("/api/attachments")]
+pub async fn create_upload(
+    auth: Authenticated<scopes::Messages>,
+    payload: web::Json<CreateUploadPayload>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let payload = payload.into_inner();
+
+    let file_name = payload.file_name.trim();
+    if file_name.is_empty() || file_name.len() > 255 || file_name.contains(['/', '\\']) {
+        return Err(Error::BadRequest("Invalid file name".into()));
+    }
+
+    if payload.content_type.len() > 255 || payload.content_type.parse::<mime::Mime>().is_err() {
+        return Err(Error::BadRequest("Invalid content type".into()));
+    }
+
+    if !(1..=MAX_FILE_SIZE).contains(&payload.size) {
+        return Err(Error::BadRequest(
+            "Attachments must be between 1 byte and 100MB".into(),
+        ));
+    }
+
+    let chunk_size = payload.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
+    if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+        return Err(Error::BadRequest(format!(
+            "Chunk size must be between {MIN_CHUNK_SIZE} and {MAX_CHUNK_SIZE} bytes"
+        )));
+    }
+
+    let sha256 = payload.sha256.to_ascii_lowercase();
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::BadRequest("sha256 must be 64 hex characters".into()));
+    }
+
+    let mut tx = app_state.pool().begin().await?;
+
+    // Locking the uploader keeps concurrent creates from overshooting the limits.
+    sqlx::query!(
+        "SELECT id FROM clients WHERE id = $1 FOR UPDATE",
+        id.to_string()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let unsent = sqlx::query!(
+        "SELECT COUNT(*) AS \"count!\", COALESCE(SUM(size), 0)::BIGINT AS \"size!\"
+        FROM attachments WHERE uploader_id = $1 AND message_id IS NULL",
+        id.to_string()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if unsent.count >= MAX_UNSENT_UPLOADS {
+        return Err(Error::Conflict(format!(
+            "At most {MAX_UNSENT_UPLOADS} uploads may be unsent at once"
+        )));
+    }
+
+    if unsent.size + payload.size > MAX_UNSENT_SIZE {
+        return Err(Error::Conflict(
+            "Unsent uploads may not exceed 1GB in total".into(),
+        ));
+    }
+
+    let attachment_id = app_state.ulid.lock().await.generate()?.to_string();
+
+    let row = sqlx::query_as!(
+        AttachmentRow,
+        "INSERT INTO attachments (id, uploader_id, file_name, content_type, size, chunk_size, sha256)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, uploader_id, message_id, file_name, content_type, size, chunk_size, sha256, completed_at, width, height, blurhash, has_thumbnail",
+        attachment_id,
+        id.to_string(),
+        file_name,
+        payload.content_type,
+        payload.size,
+        chunk_size,
+        sha256
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Created().json(row.into_attachment(Some(Vec::new()))))
+}
+
+#[actix_web::get("/api/attachments/{id}")]
+pub async fn get_attachment(
+    auth: Authenticated<scopes::Messages>,
+    attachment_id: web::Path<String>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let row = find_visible(&app_state, id, &attachment_id).await?;
+
+    let received = match row.completed_at {
+        Some(_) => None,
+        None => Some(attachments::received_chunks(&app_state, &row.id).await?),
+    };
+
+    Ok(HttpResponse::Ok().json(row.into_attachment(received)))
+}
+
+/// Uploading a chunk that was already received simply replaces it, so
+/// clients can retry without checking first.
+#[actix_web::put("/api/attachments/{id}/chunks/{index}")]
+pub async fn upload_chunk(
+    auth: Authenticated<scopes::Messages>,
+    path: web::Path<(String, u32)>,
+    payload: web::Payload,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let (attachment_id, index) = path.into_inner();
+    let row = find_pending(&app_state, id, &attachment_id).await?;
+
+    if index >= row.chunk_count() {
+        return Err(Error::BadRequest(format!(
+            "Chunk index must be below {}",
+            row.chunk_count()
+        )));
+    }
+
+    let expected = row.chunk_len(index);
+    let bytes = payload
+        .to_bytes_limited(expected)
+        .await
+        .map_err(|_| Error::BadRequest(format!("Chunk {index} must be {expected} bytes")))?
+        .map_err(|e| Error::BadRequest(e.to_string()))?;
+
+    if bytes.len() != expected {
+        return Err(Error::BadRequest(format!(
+            "Chunk {index} must be {expected} bytes"
+        )));
+    }
+
+    let path = attachments::chunk_path(&app_state, &row.id, index);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let tmp = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+    tokio::fs::write(&tmp, &bytes).await?;
+    tokio::fs::rename(&tmp, &path).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "received_chunks": attachments::received_chunks(&app_state, &row.id).await?
+    })))
+}
+
+#[actix_web::post("/api/attachments/{id}/finalize")]
+pub async fn finalize_upload(
+    auth: Authenticated<scopes::Messages>,
+    attachment_id: web::Path<String>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let row = find_pending(&app_state, id, &attachment_id).await?;
+
+    let received = attachments::received_chunks(&app_state, &row.id).await?;
+    let missing: Vec<u32> = (0..row.chunk_count())
+        .filter(|index| received.binary_search(index).is_err())
+        .collect();
+
+    if !missing.is_empty() {
+        return Err(Error::IncompleteUpload {
+            missing_chunks: missing,
+        });
+    }
+
+    let (tmp, size, sha256) = attachments::assemble(&app_state, &row.id, row.chunk_count()).await?;
+
+    if size != row.size as u64 || sha256 != row.sha256 {
+        tokio::fs::remove_file(&tmp).await?;
+        attachments::discard_staging(&app_state, &row.id).await?;
+
+        return Err(Error::BadRequest(
+            "Uploaded content does not match the declared checksum, upload it again".into(),
+        ));
+    }
+
+    attachments::store_blob(&app_state, &tmp, &sha256).await?;
+    attachments::discard_staging(&app_state, &row.id).await?;
+
+    let row = sqlx::query_as!(
+        AttachmentRow,
+        "UPDATE attachments SET completed_at = NOW() WHERE id = $1
+        RETURNING id, uploader_id, message_id, file_name, content_type, size, chunk_size, sha256, completed_at, width, height, blurhash, has_thumbnail",
+        row.id
+    )
+    .fetch_one(app_state.pool())
+    .await?;
+
+    thumbnails::spawn(
+        app_state.get_ref().clone(),
+        srv.get_ref().clone(),
+        row.id.clone(),
+    );
+
+    Ok(HttpResponse::Ok().json(row.into_attachment(None)))
+}
+
+#[actix_web::get("/api/attachments/{id}/content")]
+pub async fn get_content(
+    req: HttpRequest,
+    auth: Authenticated<scopes::Messages>,
+    attachment_id: web::Path<String>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let row = find_visible(&app_state, id, &attachment_id).await?;
+
+    if row.completed_at.is_none() {
+        return Err(Error::NotFound);
+    }
+
+    let content_type = row
+        .content_type
+        .parse()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+
+    let disposition = match content_type.type_() {
+        mime::IMAGE | mime::VIDEO | mime::AUDIO => DispositionType::Inline,
+        _ => DispositionType::Attachment,
+    };
+
+    Ok(
+        NamedFile::open_async(attachments::blob_path(&app_state, &row.sha256))
+            .await?
+            .set_content_type(content_type)
+            .set_content_disposition(ContentDisposition {
+                disposition,
+                parameters: vec![DispositionParam::Filename(row.file_name)],
+            })
+            .respond_to(&req)
+            .map_into_boxed_body(),
+    )
+}
+
+#[actix_web::get("/api/attachments/{id}/thumbnail")]
+pub async fn get_thumbnail(
+    req: HttpRequest,
+    auth: Authenticated<scopes::Messages>,
+    attachment_id: web::Path<String>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let row = find_visible(&app_state, id, &attachment_id).await?;
+
+    if !row.has_thumbnail {
+        return Err(Error::NotFound);
+    }
+
+    Ok(
+        NamedFile::open_async(attachments::thumbnail_path(&app_state, &row.sha256))
+            .await?
+            .set_content_type(mime::IMAGE_JPEG)
+            .respond_to(&req)
+            .map_into_boxed_body(),
+    )
+}
@@ -0,0 +1,220 @@
+//! Avatars are stored on disk in a handful of fixed sizes. Clients that never
+//! uploaded one get an identicon derived from their id instead.
+//!
+//! Avatars are only served to callers that may see the user, see
+//! `users::ensure_visible`.
+
+use std::{path::PathBuf, sync::Arc};
+
+use actix::Addr;
+use actix_web::{
+    HttpRequest, HttpResponse,
+    http::header::{self, CacheControl, CacheDirective, ETag, EntityTag},
+    web,
+};
+use image::DynamicImage;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Authenticated, scopes},
+    endpoints::{
+        profiles::{broadcast_profile, load_profile},
+        users::ensure_visible,
+    },
+    error::{Error, Result},
+    types::Appstate,
+    utils::{identicon, images},
+    websocket::server::Server,
+};
+
+const SIZES: [u32; 3] = [32, 128, 512];
+const DEFAULT_SIZE: u32 = 128;
+const MAX_UPLOAD_BYTES: usize = 5 * 1024 * 1024;
+
+#[derive(Deserialize)]
+pub struct AvatarQuery {
+    pub size: Option<u32>,
+    /// Set on the urls handed out in profiles so a new upload busts caches.
+    pub v: Option<String>,
+}
+
+pub fn avatar_url(id: &Uuid, version: Option<&str>) -> String {
+    match version {
+        Some(version) => format!("/api/users/{id}/avatar?v={version}"),
+        None => format!("/api/users/{id}/avatar"),
+    }
+}
+
+fn avatar_dir(app_state: &Appstate, id: &Uuid) -> PathBuf {
+    app_state.data_dir().join("avatars").join(id.to_string())
+}
+
+/// The smallest stored size that is at least as large as the one requested.
+fn pick_size(requested: Option<u32>) -> u32 {
+    let requested = requested.unwrap_or(DEFAULT_SIZE);
+
+    SIZES
+        .into_iter()
+        .find(|size| *size >= requested)
+        .unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+fn png_response(req: &HttpRequest, body: Vec<u8>, etag: String, immutable: bool) -> HttpResponse {
+    let etag = EntityTag::new_strong(etag);
+
+    let matches = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag.to_string()));
+
+    // Private, since whether an avatar is served depends on the caller.
+    let cache = match immutable {
+        true => vec![
+            CacheDirective::Private,
+            CacheDirective::MaxAge(31_536_000),
+            CacheDirective::Extension("immutable".into(), None),
+        ],
+        false => vec![CacheDirective::Private, CacheDirective::MaxAge(3600)],
+    };
+
+    let mut response = match matches {
+        true => HttpResponse::NotModified(),
+        false => HttpResponse::Ok(),
+    };
+    response
+        .insert_header(ETag(etag))
+        .insert_header(CacheControl(cache));
+
+    match matches {
+        true => response.finish(),
+        false => response.content_type("image/png").body(body),
+    }
+}
+
+#[actix_web::get("/api/users/{id}/avatar")]
+pub async fn get_avatar(
+    auth: Authenticated<scopes::Contacts>,
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    query: web::Query<AvatarQuery>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = id.into_inner();
+    ensure_visible(&app_state, auth.id(), id).await?;
+
+    let size = pick_size(query.size);
+    let path = avatar_dir(&app_state, &id).join(format!("{size}.png"));
+
+    match tokio::fs::read(&path).await {
+        Ok(body) => {
+            let modified = tokio::fs::metadata(&path)
+                .await?
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or_default();
+
+            Ok(png_response(
+                &req,
+                body,
+                format!("{id}-{size}-{modified}"),
+                query.v.is_some(),
+            ))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let body = web::block(move || {
+                images::encode_png(&DynamicImage::ImageRgba8(identicon::render(&id, size)))
+            })
+            .await
+            .map_err(actix_web::Error::from)??;
+
+            // Identicons never change for a given id.
+            Ok(png_response(
+                &req,
+                body,
+                format!("identicon-{id}-{size}"),
+                true,
+            ))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[actix_web::put("/api/self/avatar")]
+pub async fn upload_avatar(
+    auth: Authenticated<scopes::Profile>,
+    payload: web::Payload,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+
+    let bytes = payload
+        .to_bytes_limited(MAX_UPLOAD_BYTES)
+        .await
+        .map_err(|_| Error::BadRequest("Avatar must be at most 5MB".into()))?
+        .map_err(|e| Error::BadRequest(e.to_string()))?;
+
+    let resized = web::block(move || -> image::ImageResult<Vec<(u32, Vec<u8>)>> {
+        let image = images::decode(&bytes)?;
+
+        SIZES
+            .into_iter()
+            .map(|size| Ok((size, images::encode_png(&images::square(&image, size))?)))
+            .collect()
+    })
+    .await
+    .map_err(actix_web::Error::from)??;
+
+    let dir = avatar_dir(&app_state, &id);
+    tokio::fs::create_dir_all(&dir).await?;
+
+    for (size, png) in resized {
+        // Unique, so concurrent uploads never write to the same file.
+        let tmp = dir.join(format!("{size}.png.{}.tmp", Uuid::new_v4().simple()));
+        tokio::fs::write(&tmp, png).await?;
+        tokio::fs::rename(&tmp, dir.join(format!("{size}.png"))).await?;
+    }
+
+    let version = app_state.ulid.lock().await.generate()?.to_string();
+
+    sqlx::query!(
+        "INSERT INTO profiles (client_id, avatar_version) VALUES ($1, $2)
+        ON CONFLICT (client_id) DO UPDATE SET avatar_version = EXCLUDED.avatar_version, updated_at = NOW()",
+        id.to_string(),
+        version
+    )
+    .execute(app_state.pool())
+    .await?;
+
+    broadcast_profile(&app_state, &srv, id).await?;
+
+    Ok(HttpResponse::Ok().json(load_profile(&app_state, id).await?))
+}
+
+#[actix_web::delete("/api/self/avatar")]
+pub async fn delete_avatar(
+    auth: Authenticated<scopes::Profile>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+
+    sqlx::query!(
+        "UPDATE profiles SET avatar_version = NULL, updated_at = NOW() WHERE client_id = $1",
+        id.to_string()
+    )
+    .execute(app_state.pool())
+    .await?;
+
+    match tokio::fs::remove_dir_all(avatar_dir(&app_state, &id)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    broadcast_profile(&app_state, &srv, id).await?;
+
+    Ok(HttpResponse::Ok().json(load_profile(&app_state, id).await?))
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use actix::Addr;
+use actix_web::{HttpResponse, web};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Authenticated, scopes},
+    error::{Error, Result},
+    types::Appstate,
+    websocket::{
+        actions::BlockChanged,
+        server::{Enqueue, Server},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct BlockPayload {
+    pub id: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct BlockedUser {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Whether either client has blocked the other.
+pub async fn blocked_between(app_state: &Appstate, a: Uuid, b: Uuid) -> Result<bool> {
+    Ok(sqlx::query!(
+        "SELECT client_id FROM blocks
+        WHERE (client_id = $1 AND blocked_id = $2) OR (client_id = $2 AND blocked_id = $1)",
+        a.to_string(),
+        b.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .is_some())
+}
+
+#[actix_web::post("/api/blocks")]
+pub async fn block(
+    auth: Authenticated<scopes::Contacts>,
+    payload: web::Json<BlockPayload>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+
+    if id == payload.id {
+        return Err(Error::BadRequest("You cannot block yourself".into()));
+    }
+
+    let mut tx = app_state.pool().begin().await?;
+
+    let inserted = sqlx::query!(
+        "INSERT INTO blocks (client_id, blocked_id)
+        SELECT $1, id FROM clients WHERE id = $2
+        ON CONFLICT DO NOTHING",
+        id.to_string(),
+        payload.id.to_string()
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    // Pending requests in either direction are dropped along with the block.
+    sqlx::query!(
+        "DELETE FROM contact_requests
+        WHERE (sender_id = $1 AND recipient_id = $2) OR (sender_id = $2 AND recipient_id = $1)",
+        id.to_string(),
+        payload.id.to_string()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    if inserted > 0 {
+        srv.enqueue(BlockChanged {
+            id,
+            other: payload.id,
+            blocked: true,
+        });
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[actix_web::delete("/api/blocks/{id}")]
+pub async fn unblock(
+    auth: Authenticated<scopes::Contacts>,
+    other: web::Path<Uuid>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let other = other.into_inner();
+
+    let removed = sqlx::query!(
+        "DELETE FROM blocks WHERE client_id = $1 AND blocked_id = $2",
+        id.to_string(),
+        other.to_string()
+    )
+    .execute(app_state.pool())
+    .await?
+    .rows_affected();
+
+    if removed == 0 {
+        return Err(Error::NotFound);
+    }
+
+    srv.enqueue(BlockChanged {
+        id,
+        other,
+        blocked: false,
+    });
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[actix_web::get("/api/blocks")]
+pub async fn list_blocked(
+    auth: Authenticated<scopes::Contacts>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+
+    let blocked = sqlx::query_as!(
+        BlockedUser,
+        "SELECT blocked_id AS id, created_at FROM blocks WHERE client_id = $1 ORDER BY created_at",
+        id.to_string()
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(blocked))
+}
@@ -0,0 +1,368 @@
+use std::sync::Arc;
+
+use actix::Addr;
+use actix_web::{HttpResponse, web};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Authenticated, scopes},
+    endpoints::blocks::blocked_between,
+    error::{Error, Result},
+    types::Appstate,
+    websocket::{
+        actions::{ContactAdded, ContactRemoved, GetOnline, Notify},
+        event::{ServerEvent, ServerEventType},
+        server::{Enqueue, Server},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct ContactRequestPayload {
+    pub id: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct ContactRequest {
+    pub id: String,
+    pub sender_id: String,
+    pub recipient_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct Contact {
+    pub id: String,
+    pub online: bool,
+    pub since: DateTime<Utc>,
+}
+
+async fn are_contacts(app_state: &Appstate, a: Uuid, b: Uuid) -> Result<bool> {
+    Ok(sqlx::query!(
+        "SELECT client_id FROM contacts WHERE client_id = $1 AND contact_id = $2",
+        a.to_string(),
+        b.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .is_some())
+}
+
+/// Deletes a pending request. Fails with `NotFound` when it was already
+/// taken, e.g. by a concurrent accept.
+async fn take_request(conn: &mut sqlx::PgConnection, request_id: &str) -> Result<()> {
+    let deleted = sqlx::query!("DELETE FROM contact_requests WHERE id = $1", request_id)
+        .execute(conn)
+        .await?
+        .rows_affected();
+
+    match deleted {
+        0 => Err(Error::NotFound),
+        _ => Ok(()),
+    }
+}
+
+/// Looks up a pending request, returning its sender and recipient.
+async fn find_request(app_state: &Appstate, request_id: &str) -> Result<(Uuid, Uuid)> {
+    let row = sqlx::query!(
+        "SELECT sender_id, recipient_id FROM contact_requests WHERE id = $1",
+        request_id
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    Ok((
+        Uuid::parse_str(&row.sender_id).map_err(|_| Error::NotFound)?,
+        Uuid::parse_str(&row.recipient_id).map_err(|_| Error::NotFound)?,
+    ))
+}
+
+/// Replaces a pending request with the contact it asked for. Both happen in
+/// one transaction, so a failure never loses the request.
+async fn accept(
+    app_state: &Appstate,
+    srv: &Addr<Server>,
+    request_id: &str,
+    a: Uuid,
+    b: Uuid,
+) -> Result<()> {
+    let mut tx = app_state.pool().begin().await?;
+
+    take_request(&mut tx, request_id).await?;
+
+    for (client, contact) in [(a, b), (b, a)] {
+        sqlx::query!(
+            "INSERT INTO contacts (client_id, contact_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            client.to_string(),
+            contact.to_string()
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    srv.enqueue(ContactAdded { a, b });
+
+    Ok(())
+}
+
+#[actix_web::post("/api/contacts/requests")]
+pub async fn send_request(
+    auth: Authenticated<scopes::Contacts>,
+    payload: web::Json<ContactRequestPayload>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let sender = auth.id();
+    let recipient = payload.id;
+
+    if sender == recipient {
+        return Err(Error::BadRequest("You cannot add yourself".into()));
+    }
+
+    if sqlx::query!(
+        "SELECT id FROM clients WHERE id = $1 AND discoverable",
+        recipient.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .is_none()
+    {
+        return Err(Error::NotFound);
+    }
+
+    if blocked_between(&app_state, sender, recipient).await? {
+        return Err(Error::NotFound);
+    }
+
+    if are_contacts(&app_state, sender, recipient).await? {
+        return Err(Error::Conflict("Already a contact".into()));
+    }
+
+    // Both sides asking for each other is as good as an acceptance.
+    if let Some(reverse) = sqlx::query!(
+        "SELECT id FROM contact_requests WHERE sender_id = $1 AND recipient_id = $2",
+        recipient.to_string(),
+        sender.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    {
+        accept(&app_state, &srv, &reverse.id, sender, recipient).await?;
+
+        srv.enqueue(Notify {
+            to: recipient,
+            event: ServerEvent::new(
+                ServerEventType::ContactRequestAccepted,
+                json!({"requestId": reverse.id, "id": sender}),
+            ),
+        });
+
+        return Ok(HttpResponse::Ok().json(json!({"status": "accepted"})));
+    }
+
+    let id = app_state.ulid.lock().await.generate()?.to_string();
+
+    let created = sqlx::query!(
+        "INSERT INTO contact_requests (id, sender_id, recipient_id) VALUES ($1, $2, $3)
+        ON CONFLICT (sender_id, recipient_id) DO NOTHING
+        RETURNING created_at",
+        id,
+        sender.to_string(),
+        recipient.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .ok_or_else(|| Error::Conflict("Request already pending".into()))?;
+
+    let request = ContactRequest {
+        id,
+        sender_id: sender.to_string(),
+        recipient_id: recipient.to_string(),
+        created_at: created.created_at,
+    };
+
+    srv.enqueue(Notify {
+        to: recipient,
+        event: ServerEvent::new(
+            ServerEventType::ContactRequest,
+            json!({"requestId": request.id, "id": sender, "createdAt": request.created_at}),
+        ),
+    });
+
+    Ok(HttpResponse::Created().json(request))
+}
+
+#[actix_web::get("/api/contacts/requests")]
+pub async fn list_requests(
+    auth: Authenticated<scopes::Contacts>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id().to_string();
+
+    let requests = sqlx::query_as!(
+        ContactRequest,
+        "SELECT id, sender_id, recipient_id, created_at FROM contact_requests
+        WHERE sender_id = $1 OR recipient_id = $1
+        ORDER BY id",
+        id
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    let (incoming, outgoing): (Vec<_>, Vec<_>) =
+        requests.into_iter().partition(|r| r.recipient_id == id);
+
+    Ok(HttpResponse::Ok().json(json!({"incoming": incoming, "outgoing": outgoing})))
+}
+
+#[actix_web::post("/api/contacts/requests/{id}/accept")]
+pub async fn accept_request(
+    auth: Authenticated<scopes::Contacts>,
+    request_id: web::Path<String>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let caller = auth.id();
+    let (sender, recipient) = find_request(&app_state, &request_id).await?;
+
+    if recipient != caller {
+        return Err(Error::NotFound);
+    }
+
+    accept(&app_state, &srv, &request_id, sender, recipient).await?;
+
+    srv.enqueue(Notify {
+        to: sender,
+        event: ServerEvent::new(
+            ServerEventType::ContactRequestAccepted,
+            json!({"requestId": request_id.as_str(), "id": recipient}),
+        ),
+    });
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[actix_web::post("/api/contacts/requests/{id}/decline")]
+pub async fn decline_request(
+    auth: Authenticated<scopes::Contacts>,
+    request_id: web::Path<String>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let caller = auth.id();
+    let (sender, recipient) = find_request(&app_state, &request_id).await?;
+
+    if recipient != caller {
+        return Err(Error::NotFound);
+    }
+
+    take_request(&mut *app_state.pool().acquire().await?, &request_id).await?;
+
+    srv.enqueue(Notify {
+        to: sender,
+        event: ServerEvent::new(
+            ServerEventType::ContactRequestDeclined,
+            json!({"requestId": request_id.as_str()}),
+        ),
+    });
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[actix_web::delete("/api/contacts/requests/{id}")]
+pub async fn cancel_request(
+    auth: Authenticated<scopes::Contacts>,
+    request_id: web::Path<String>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let caller = auth.id();
+    let (sender, recipient) = find_request(&app_state, &request_id).await?;
+
+    if sender != caller {
+        return Err(Error::NotFound);
+    }
+
+    take_request(&mut *app_state.pool().acquire().await?, &request_id).await?;
+
+    srv.enqueue(Notify {
+        to: recipient,
+        event: ServerEvent::new(
+            ServerEventType::ContactRequestCancelled,
+            json!({"requestId": request_id.as_str()}),
+        ),
+    });
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[actix_web::get("/api/contacts")]
+pub async fn list_contacts(
+    auth: Authenticated<scopes::Contacts>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+
+    let rows = sqlx::query!(
+        "SELECT contact_id, created_at FROM contacts WHERE client_id = $1 ORDER BY created_at",
+        id.to_string()
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    let ids = rows
+        .iter()
+        .filter_map(|row| Uuid::parse_str(&row.contact_id).ok())
+        .collect();
+    let online = srv.send(GetOnline { ids }).await?;
+
+    let contacts: Vec<Contact> = rows
+        .into_iter()
+        .map(|row| Contact {
+            online: Uuid::parse_str(&row.contact_id).is_ok_and(|id| online.contains(&id)),
+            id: row.contact_id,
+            since: row.created_at,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(contacts))
+}
+
+#[actix_web::delete("/api/contacts/{id}")]
+pub async fn remove_contact(
+    auth: Authenticated<scopes::Contacts>,
+    contact: web::Path<Uuid>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let contact = contact.into_inner();
+
+    let removed = sqlx::query!(
+        "DELETE FROM contacts
+        WHERE (client_id = $1 AND contact_id = $2) OR (client_id = $2 AND contact_id = $1)",
+        id.to_string(),
+        contact.to_string()
+    )
+    .execute(app_state.pool())
+    .await?
+    .rows_affected();
+
+    if removed == 0 {
+        return Err(Error::NotFound);
+    }
+
+    srv.enqueue(ContactRemoved { a: id, b: contact });
+    srv.enqueue(Notify {
+        to: contact,
+        event: ServerEvent::new(ServerEventType::ContactRemoved, json!({"id": id})),
+    });
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,212 @@
+//! Conversations are one-to-one, so each side addresses one by the id of the
+//! peer on the other end.
+
+use std::sync::Arc;
+
+use actix::Addr;
+use actix_web::{HttpResponse, web};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Authenticated, scopes},
+    error::{Error, Result},
+    messages,
+    types::Appstate,
+    websocket::{
+        actions::{MuteChanged, ToConversation},
+        event::{ServerEvent, ServerEventType},
+        server::{Enqueue, Server},
+    },
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    /// Id of the oldest message already loaded.
+    pub before: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[actix_web::get("/api/conversations/{peer}/messages")]
+pub async fn history(
+    auth: Authenticated<scopes::Messages>,
+    peer: web::Path<Uuid>,
+    query: web::Query<HistoryQuery>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let messages = messages::history(
+        &app_state,
+        id,
+        peer.into_inner(),
+        query.before.as_deref(),
+        limit,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "next_before": (messages.len() as i64 == limit)
+            .then(|| messages.last().map(|m| m.id.clone()))
+            .flatten(),
+        "messages": messages,
+    })))
+}
+
+const MIN_DISAPPEAR_AFTER: i32 = 5;
+const MAX_DISAPPEAR_AFTER: i32 = 365 * 24 * 60 * 60;
+
+#[derive(Deserialize)]
+pub struct DisappearingPayload {
+    /// Seconds after which new messages expire, or null to keep them.
+    pub disappear_after: Option<i32>,
+}
+
+#[actix_web::get("/api/conversations/{peer}/disappearing")]
+pub async fn get_disappearing(
+    auth: Authenticated<scopes::Messages>,
+    peer: web::Path<Uuid>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+
+    let disappear_after = sqlx::query_scalar!(
+        "SELECT disappear_after FROM conversation_settings
+        WHERE low_id = LEAST($1, $2) AND high_id = GREATEST($1, $2)",
+        id.to_string(),
+        peer.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .flatten();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"disappear_after": disappear_after})))
+}
+
+/// The timer is shared by both participants and only applies to messages sent
+/// after it changed.
+#[actix_web::put("/api/conversations/{peer}/disappearing")]
+pub async fn set_disappearing(
+    auth: Authenticated<scopes::Messages>,
+    peer: web::Path<Uuid>,
+    payload: web::Json<DisappearingPayload>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let peer = peer.into_inner();
+
+    if id == peer {
+        return Err(Error::BadRequest(
+            "Cannot set a timer on a conversation with yourself".into(),
+        ));
+    }
+
+    if let Some(seconds) = payload.disappear_after
+        && !(MIN_DISAPPEAR_AFTER..=MAX_DISAPPEAR_AFTER).contains(&seconds)
+    {
+        return Err(Error::BadRequest(format!(
+            "Timer must be between {MIN_DISAPPEAR_AFTER} seconds and 365 days"
+        )));
+    }
+
+    messages::authorize(&app_state, id, peer).await?;
+
+    sqlx::query!(
+        "INSERT INTO conversation_settings (low_id, high_id, disappear_after)
+        VALUES (LEAST($1, $2), GREATEST($1, $2), $3)
+        ON CONFLICT (low_id, high_id) DO UPDATE
+        SET disappear_after = EXCLUDED.disappear_after, updated_at = NOW()",
+        id.to_string(),
+        peer.to_string(),
+        payload.disappear_after
+    )
+    .execute(app_state.pool())
+    .await?;
+
+    srv.enqueue(ToConversation {
+        author: id,
+        peer,
+        event: ServerEvent::new(
+            ServerEventType::DisappearingChanged,
+            serde_json::json!({
+                "from": id,
+                "to": peer,
+                "disappearAfter": payload.disappear_after,
+            }),
+        ),
+    });
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"disappear_after": payload.disappear_after})))
+}
+
+#[actix_web::put("/api/conversations/{peer}/mute")]
+pub async fn mute(
+    auth: Authenticated<scopes::Messages>,
+    peer: web::Path<Uuid>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let peer = peer.into_inner();
+
+    let muted = sqlx::query!(
+        "INSERT INTO mutes (client_id, peer_id)
+        SELECT $1, id FROM clients WHERE id = $2
+        ON CONFLICT DO NOTHING",
+        id.to_string(),
+        peer.to_string()
+    )
+    .execute(app_state.pool())
+    .await?
+    .rows_affected();
+
+    if muted > 0 {
+        srv.enqueue(MuteChanged {
+            id,
+            peer,
+            muted: true,
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"muted": true})))
+}
+
+#[actix_web::delete("/api/conversations/{peer}/mute")]
+pub async fn unmute(
+    auth: Authenticated<scopes::Messages>,
+    peer: web::Path<Uuid>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let peer = peer.into_inner();
+
+    let removed = sqlx::query!(
+        "DELETE FROM mutes WHERE client_id = $1 AND peer_id = $2",
+        id.to_string(),
+        peer.to_string()
+    )
+    .execute(app_state.pool())
+    .await?
+    .rows_affected();
+
+    if removed == 0 {
+        return Err(Error::NotFound);
+    }
+
+    srv.enqueue(MuteChanged {
+        id,
+        peer,
+        muted: false,
+    });
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"muted": false})))
+}
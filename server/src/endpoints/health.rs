@@ -0,0 +1,113 @@
+//! Probes for deployments. Liveness only shows the process is serving
+//! requests; readiness also checks everything a request depends on.
+
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use actix::Addr;
+use actix_web::{HttpResponse, rt::time::Instant, web};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::Connection;
+
+use crate::{
+    types::Appstate,
+    websocket::{actions::Probe, server::Server},
+};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Version of the last script in init-scripts, which each script records in
+/// `schema_migrations`. Postgres applies the scripts when it initialises its
+/// volume, so a database created before a script was added lacks its row.
+const SCHEMA_VERSION: i32 = 15;
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+async fn check(probe: impl Future<Output = Result<(), String>>) -> Check {
+    let started = Instant::now();
+
+    let result = match actix_web::rt::time::timeout(CHECK_TIMEOUT, probe).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("timed out after {}s", CHECK_TIMEOUT.as_secs())),
+    };
+
+    Check {
+        ok: result.is_ok(),
+        latency_ms: started.elapsed().as_millis(),
+        error: result.err(),
+    }
+}
+
+async fn ping_database(app_state: &Appstate) -> Result<(), String> {
+    let mut conn = app_state
+        .pool()
+        .acquire()
+        .await
+        .map_err(|e| e.to_string())?;
+    conn.ping().await.map_err(|e| e.to_string())
+}
+
+async fn probe_server(srv: &Addr<Server>) -> Result<(), String> {
+    srv.send(Probe)
+        .timeout(CHECK_TIMEOUT)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Every script in init-scripts up to `SCHEMA_VERSION` must have been applied.
+async fn check_schema(app_state: &Appstate) -> Result<(), String> {
+    let applied = sqlx::query_scalar!("SELECT version FROM schema_migrations")
+        .fetch_all(app_state.pool())
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let missing = (0..=SCHEMA_VERSION)
+        .filter(|version| !applied.contains(version))
+        .map(|version| format!("{version:02}"))
+        .collect::<Vec<_>>();
+
+    match missing.is_empty() {
+        true => Ok(()),
+        false => Err(format!("scripts not applied: {}", missing.join(", "))),
+    }
+}
+
+#[actix_web::get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({"status": "ok"}))
+}
+
+#[actix_web::get("/readyz")]
+pub async fn readyz(
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> HttpResponse {
+    let (database, server, schema) = tokio::join!(
+        check(ping_database(&app_state)),
+        check(probe_server(&srv)),
+        check(check_schema(&app_state)),
+    );
+
+    let ready = database.ok && server.ok && schema.ok;
+    let body = json!({
+        "status": if ready { "ok" } else { "unavailable" },
+        "checks": {
+            "database": database,
+            "server": server,
+            "schema": schema,
+        },
+    });
+
+    match ready {
+        true => HttpResponse::Ok().json(body),
+        false => HttpResponse::ServiceUnavailable().json(body),
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use actix_web::{HttpResponse, web};
+use serde::Deserialize;
+
+use crate::{
+    auth::{Authenticated, scopes},
+    error::{Error, Result},
+    messages,
+    types::Appstate,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Deserialize)]
+pub struct ThreadQuery {
+    /// Id of the newest reply already loaded.
+    pub after: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// The thread a message belongs to. Any message of the thread can be used to
+/// open it, not only the root.
+#[actix_web::get("/api/messages/{id}/thread")]
+pub async fn thread(
+    auth: Authenticated<scopes::Messages>,
+    message_id: web::Path<String>,
+    query: web::Query<ThreadQuery>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let (root, replies) =
+        messages::thread(&app_state, id, &message_id, query.after.as_deref(), limit).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "next_after": (replies.len() as i64 == limit)
+            .then(|| replies.last().map(|m| m.id.clone()))
+            .flatten(),
+        "root": root,
+        "replies": replies,
+    })))
+}
+
+/// Earlier versions of an edited message, visible to both participants.
+#[actix_web::get("/api/messages/{id}/edits")]
+pub async fn edits(
+    auth: Authenticated<scopes::Messages>,
+    message_id: web::Path<String>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+
+    if !messages::is_participant(&app_state, id, &message_id).await? {
+        return Err(Error::NotFound);
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "edits": messages::edits(&app_state, &message_id).await?
+    })))
+}
@@ -0,0 +1,14 @@
+use std::sync::Arc;
+
+use actix_web::{HttpResponse, web};
+
+use crate::{error::Result, metrics::METRICS, types::Appstate};
+
+/// Prometheus scrape target. Like the rest of the API it is bound to
+/// localhost, so it is left unauthenticated.
+#[actix_web::get("/metrics")]
+pub async fn metrics(app_state: web::Data<Arc<Appstate>>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(METRICS.render(app_state.pool())?))
+}
@@ -1,108 +1,205 @@
+pub mod admin;
+pub mod attachments;
+pub mod avatars;
+pub mod blocks;
+pub mod contacts;
+pub mod conversations;
+pub mod health;
+pub mod messages;
+pub mod metrics;
+pub mod profiles;
+pub mod search;
+pub mod users;
+
 use std::sync::Arc;
 
-use actix::Addr;
-use actix_web::{HttpResponse, web};
+use actix_web::{HttpRequest, HttpResponse, web};
 use chrono::{Duration, Utc};
 use serde::Deserialize;
-use ulid::Ulid;
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
 use crate::{
+    auth::{Authenticated, scopes},
     error::{Error, Result},
-    types::{Appstate, Claims},
-    utils::extract_auth_token,
-    websocket::{
-        GetUID,
-        actions::ChangeId,
-        server::{GetClient, GetClients},
-    },
+    metrics::METRICS,
+    types::{Appstate, Claims, Scope},
 };
 
 #[derive(Deserialize)]
-pub struct Payload {
-    pub id: Uuid,
+pub struct RecoverPayload {
+    pub recovery_key: String,
 }
 
-#[actix_web::get("/api/get_client")]
-pub async fn get_client(
-    payload: web::Json<Payload>,
-    srv: web::Data<Addr<crate::websocket::server::Server>>,
-) -> Result<Option<String>> {
-    let id = match srv
-        .send(GetClient { id: payload.id })
-        .await
-        .map_err(|e| Error::WebsocketServer(e.to_string()))?
-    {
-        Some(client) => Some(client.send(GetUID).await?),
-        None => None,
-    };
+/// How long an issued token stays valid. Clients get a new one from
+/// `/client/refresh` before it runs out.
+const TOKEN_LIFETIME: Duration = Duration::hours(1);
+
+/// Marks the claims as issued now, valid for [`TOKEN_LIFETIME`].
+fn stamp(claims: &mut Claims) {
+    let now = Utc::now();
+
+    claims.iat = now.timestamp() as usize;
+    claims.exp = now
+        .checked_add_signed(TOKEN_LIFETIME)
+        .expect("valid timestamp")
+        .timestamp() as usize;
+}
 
-    Ok(id)
+/// Picks up roles, and the scopes they grant, changed since the token was
+/// issued.
+async fn refresh_roles(app_state: &Appstate, claims: &mut Claims) -> Result<()> {
+    claims.roles = sqlx::query_scalar!(
+        "SELECT roles FROM clients WHERE id = $1",
+        claims.id.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .ok_or(Error::Unauthorized)?;
+    claims.scopes = Scope::for_roles(&claims.roles);
+
+    Ok(())
+}
+
+/// Hash stored for a recovery key. Dashes, spaces and case are ignored, so
+/// the key can also be typed back in as a UUID.
+fn hash_recovery_key(key: &str) -> String {
+    let key = key.trim().to_ascii_lowercase().replace(['-', ' '], "");
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// A new recovery key, 122 random bits in hex.
+fn recovery_key() -> String {
+    Uuid::new_v4().simple().to_string()
 }
 
 #[actix_web::post("/client/auth")]
-pub async fn authenticate(app_state: web::Data<Arc<Appstate>>) -> Result<HttpResponse> {
+pub async fn authenticate(
+    req: HttpRequest,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    app_state.register_limiter.check_peer(&req)?;
+
     let id = Uuid::new_v4();
+    let mut claims = Claims {
+        id,
+        exp: 0,
+        iat: 0,
+        roles: Vec::new(),
+        scopes: Scope::for_roles(&[]),
+    };
+    stamp(&mut claims);
 
-    let token = app_state.token_manager.generate_token(&Claims {
-        id: id,
-        exp: Utc::now()
-            .checked_add_signed(Duration::hours(1))
-            .expect("valid timestamp")
-            .timestamp() as usize,
-    })?;
+    let token = app_state.token_manager.generate_token(&claims)?;
 
     let tx = app_state.pool().begin().await?;
 
-    sqlx::query!("INSERT INTO clients (id) VALUES ($1)", id.to_string())
-        .execute(app_state.pool())
-        .await?;
+    let recovery_key = recovery_key();
+
+    sqlx::query!(
+        "INSERT INTO clients (id, recovery_hash) VALUES ($1, $2)",
+        id.to_string(),
+        hash_recovery_key(&recovery_key)
+    )
+    .execute(app_state.pool())
+    .await?;
 
     tx.commit().await?;
 
-    Ok(HttpResponse::Ok().json(serde_json::json!({"access_token": token})))
+    // The key is only shown this once. The client keeps it to show it to the
+    // user.
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "access_token": token,
+        "recovery_key": recovery_key,
+    })))
+}
+
+#[actix_web::post("/api/self/changeid")]
+pub async fn change_id(
+    auth: Authenticated<scopes::Profile>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let mut claims = auth.claims;
+    refresh_roles(&app_state, &mut claims).await?;
+
+    let new_token = app_state.token_manager.generate_token(&claims)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"access_token": new_token})))
 }
 
-#[actix_web::get("/api/clients")]
-pub async fn get_clients(srv: web::Data<Addr<crate::websocket::server::Server>>) -> Result<String> {
-    let clients = srv
-        .send(GetClients)
-        .await
-        .map_err(|e| Error::WebsocketServer(e.to_string()))?;
+/// Trades a token that is still valid for one with a new expiry, e.g. to
+/// answer AUTH_EXPIRING on the websocket.
+#[actix_web::post("/client/refresh")]
+pub async fn refresh(
+    auth: Authenticated<scopes::Messages>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let mut claims = auth.claims;
+    refresh_roles(&app_state, &mut claims).await?;
+    stamp(&mut claims);
+
+    let token = app_state.token_manager.generate_token(&claims)?;
 
-    Ok(serde_json::to_string(&clients)?)
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "access_token": token,
+        "expires_at": claims.exp,
+    })))
 }
 
-#[actix_web::get("/api/clients/{id}")]
-pub async fn get_client_by_id(
-    id: web::Path<Uuid>,
-    srv: web::Data<Addr<crate::websocket::server::Server>>,
-) -> Result<Option<String>> {
-    let id = match srv
-        .send(GetClient {
-            id: id.into_inner(),
-        })
-        .await
-        .map_err(|e| Error::WebsocketServer(e.to_string()))?
-    {
-        Some(client) => Some(client.send(GetUID).await?),
-        None => None,
+/// Exchanges a recovery key for a token of the client it was issued to, for
+/// restoring an identity whose token was lost.
+#[actix_web::post("/client/recover")]
+pub async fn recover(
+    payload: web::Json<RecoverPayload>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let Some(client) = sqlx::query!(
+        "SELECT id, roles, banned_at IS NOT NULL AS \"banned!\" FROM clients WHERE recovery_hash = $1",
+        hash_recovery_key(&payload.recovery_key)
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    else {
+        METRICS.auth_failure(&Error::Unauthorized);
+        return Err(Error::Unauthorized);
+    };
+
+    if client.banned {
+        METRICS.auth_failure(&Error::Forbidden);
+        return Err(Error::Forbidden);
+    }
+
+    let mut claims = Claims {
+        id: Uuid::parse_str(&client.id)?,
+        exp: 0,
+        iat: 0,
+        scopes: Scope::for_roles(&client.roles),
+        roles: client.roles,
     };
+    stamp(&mut claims);
+
+    let token = app_state.token_manager.generate_token(&claims)?;
+    tracing::info!(client_id = %claims.id, "Identity recovered");
 
-    Ok(id)
+    Ok(HttpResponse::Ok().json(serde_json::json!({"access_token": token})))
 }
-#[actix_web::post("/api/self/changeid")]
-pub async fn change_id(
-    req: actix_web::HttpRequest,
+
+/// Replaces the caller's recovery key, e.g. for clients registered before
+/// recovery keys existed. The previous key stops working.
+#[actix_web::post("/api/self/recovery-key")]
+pub async fn reissue_recovery_key(
+    auth: Authenticated<scopes::Profile>,
     app_state: web::Data<Arc<Appstate>>,
-    srv: web::Data<Addr<crate::websocket::server::Server>>,
 ) -> Result<HttpResponse> {
-    let id = Uuid::new_v4();
-    let claims = app_state
-        .token_manager
-        .validate_token(&extract_auth_token(&req)?)?;
+    let recovery_key = recovery_key();
 
-    let new_token = app_state.token_manager.generate_token(&claims)?;
+    sqlx::query!(
+        "UPDATE clients SET recovery_hash = $2 WHERE id = $1",
+        auth.id().to_string(),
+        hash_recovery_key(&recovery_key)
+    )
+    .execute(app_state.pool())
+    .await?;
 
-    Ok(HttpResponse::Ok().json(serde_json::json!({"access_token": new_token})))
+    Ok(HttpResponse::Ok().json(serde_json::json!({"recovery_key": recovery_key})))
 }
@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use actix::Addr;
+use actix_web::{HttpResponse, web};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Authenticated, scopes},
+    endpoints::avatars::avatar_url,
+    error::{Error, Result},
+    types::Appstate,
+    websocket::{
+        actions::ProfileUpdated,
+        server::{Enqueue, Server},
+    },
+};
+
+const MAX_DISPLAY_NAME: usize = 64;
+const MAX_BIO: usize = 280;
+
+#[derive(Serialize, Clone)]
+pub struct Profile {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_url: String,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Fields left out are kept as they are, empty strings clear them.
+#[derive(Deserialize)]
+pub struct ProfilePayload {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+}
+
+fn validate(field: &str, value: &Option<String>, max: usize) -> Result<Option<String>> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+
+    let value = value.trim();
+    if value.chars().count() > max {
+        return Err(Error::BadRequest(format!(
+            "{field} must be at most {max} characters"
+        )));
+    }
+
+    Ok(Some(value.to_string()))
+}
+
+pub async fn load_profile(app_state: &Appstate, id: Uuid) -> Result<Profile> {
+    let row = sqlx::query!(
+        "SELECT p.display_name AS \"display_name?\", p.bio AS \"bio?\",
+            p.avatar_version AS \"avatar_version?\", p.updated_at AS \"updated_at?\"
+        FROM clients c
+        LEFT JOIN profiles p ON p.client_id = c.id
+        WHERE c.id = $1",
+        id.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    Ok(Profile {
+        display_name: row.display_name,
+        bio: row.bio,
+        avatar_url: avatar_url(&id, row.avatar_version.as_deref()),
+        updated_at: row.updated_at,
+    })
+}
+
+/// Pushes the client's current profile to its contacts.
+pub async fn broadcast_profile(app_state: &Appstate, srv: &Addr<Server>, id: Uuid) -> Result<()> {
+    let profile = load_profile(app_state, id).await?;
+
+    srv.enqueue(ProfileUpdated {
+        id,
+        profile: serde_json::to_value(&profile)?,
+    });
+
+    Ok(())
+}
+
+#[actix_web::get("/api/self/profile")]
+pub async fn get_profile(
+    auth: Authenticated<scopes::Profile>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+
+    Ok(HttpResponse::Ok().json(load_profile(&app_state, id).await?))
+}
+
+#[actix_web::patch("/api/self/profile")]
+pub async fn update_profile(
+    auth: Authenticated<scopes::Profile>,
+    payload: web::Json<ProfilePayload>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+
+    let display_name = validate("display_name", &payload.display_name, MAX_DISPLAY_NAME)?;
+    let bio = validate("bio", &payload.bio, MAX_BIO)?;
+
+    sqlx::query!(
+        "INSERT INTO profiles (client_id, display_name, bio)
+        VALUES ($1, NULLIF($2, ''), NULLIF($3, ''))
+        ON CONFLICT (client_id) DO UPDATE SET
+            display_name = CASE WHEN $2::text IS NULL THEN profiles.display_name ELSE EXCLUDED.display_name END,
+            bio = CASE WHEN $3::text IS NULL THEN profiles.bio ELSE EXCLUDED.bio END,
+            updated_at = NOW()",
+        id.to_string(),
+        display_name,
+        bio
+    )
+    .execute(app_state.pool())
+    .await?;
+
+    broadcast_profile(&app_state, &srv, id).await?;
+
+    Ok(HttpResponse::Ok().json(load_profile(&app_state, id).await?))
+}
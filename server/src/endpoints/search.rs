@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use actix_web::{HttpResponse, web};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Authenticated, scopes},
+    error::{Error, Result},
+    messages::{self, HIGHLIGHT_END, HIGHLIGHT_START, SearchFilters},
+    types::Appstate,
+};
+
+const MAX_QUERY_LENGTH: usize = 200;
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    /// Only the conversation with this peer.
+    pub with: Option<Uuid>,
+    pub from: Option<Uuid>,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+    pub has_attachment: Option<bool>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// A piece of a snippet, highlighted when it matched the query.
+#[derive(Serialize)]
+pub struct SnippetPart {
+    pub text: String,
+    pub highlight: bool,
+}
+
+fn split_snippet(snippet: &str) -> Vec<SnippetPart> {
+    let mut parts = Vec::new();
+
+    for (i, outside) in snippet.split(HIGHLIGHT_START).enumerate() {
+        let (highlighted, rest) = match i {
+            0 => ("", outside),
+            _ => outside.split_once(HIGHLIGHT_END).unwrap_or((outside, "")),
+        };
+
+        for (text, highlight) in [(highlighted, true), (rest, false)] {
+            if !text.is_empty() {
+                parts.push(SnippetPart {
+                    text: text.to_owned(),
+                    highlight,
+                });
+            }
+        }
+    }
+
+    parts
+}
+
+/// Full-text search over every message the caller sent or received.
+#[actix_web::get("/api/search")]
+pub async fn search(
+    auth: Authenticated<scopes::Messages>,
+    query: web::Query<SearchQuery>,
+    app_state: web::Data<Arc<Appstate>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+
+    let q = query.q.trim();
+    if q.is_empty() || q.chars().count() > MAX_QUERY_LENGTH {
+        return Err(Error::BadRequest(format!(
+            "Query must be between 1 and {MAX_QUERY_LENGTH} characters"
+        )));
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let filters = SearchFilters {
+        peer: query.with,
+        sender: query.from,
+        after: query.after,
+        before: query.before,
+        has_attachment: query.has_attachment,
+        cursor: query.cursor.as_deref(),
+    };
+
+    let results = messages::search(&app_state, id, q, &filters, limit).await?;
+
+    let next_cursor = (results.len() as i64 == limit)
+        .then(|| results.last().map(|(message, _)| message.id.clone()))
+        .flatten();
+
+    let results: Vec<_> = results
+        .into_iter()
+        .map(|(message, snippet)| {
+            serde_json::json!({
+                "message": message,
+                "snippet": split_snippet(&snippet),
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "results": results,
+        "next_cursor": next_cursor,
+    })))
+}
@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use actix::Addr;
+use actix_web::{HttpRequest, HttpResponse, web};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Authenticated, scopes},
+    endpoints::{avatars::avatar_url, profiles::Profile},
+    error::{Error, Result},
+    types::Appstate,
+    websocket::{
+        actions::{GetOnline, SetContactsOnly},
+        server::{Enqueue, Server},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct LookupQuery {
+    pub q: String,
+}
+
+#[derive(Deserialize)]
+pub struct SettingsPayload {
+    pub contacts_only: Option<bool>,
+    pub discoverable: Option<bool>,
+    pub handle: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PublicUser {
+    pub id: String,
+    pub handle: Option<String>,
+    #[serde(flatten)]
+    pub profile: Profile,
+    pub is_contact: bool,
+    /// Only ever filled in for contacts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub online: Option<bool>,
+}
+
+/// Handles are lowercase ascii letters, digits and underscores.
+fn normalize_handle(handle: &str) -> Result<String> {
+    let handle = handle.trim().trim_start_matches('@').to_lowercase();
+
+    if !(3..=32).contains(&handle.len())
+        || !handle
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(Error::BadRequest(
+            "Handles are 3 to 32 characters of a-z, 0-9 and _".into(),
+        ));
+    }
+
+    Ok(handle)
+}
+
+/// Counts a lookup against both the caller and its address.
+fn limit_lookup(app_state: &Appstate, req: &HttpRequest, caller: Uuid) -> Result<()> {
+    app_state.lookup_limiter.check(caller)?;
+    app_state.lookup_address_limiter.check_peer(req)
+}
+
+/// Fails with `NotFound` unless the caller may see `id`, by the same rules as
+/// `find_visible`. Everyone may see themselves.
+pub async fn ensure_visible(app_state: &Appstate, caller: Uuid, id: Uuid) -> Result<()> {
+    if caller == id {
+        return Ok(());
+    }
+
+    let visible = sqlx::query_scalar!(
+        "SELECT EXISTS (
+            SELECT 1 FROM clients c
+            WHERE c.id = $1
+                AND (c.discoverable
+                    OR EXISTS (SELECT 1 FROM contacts k WHERE k.client_id = $2 AND k.contact_id = c.id))
+                AND NOT EXISTS (SELECT 1 FROM blocks b WHERE b.client_id = c.id AND b.blocked_id = $2)
+        ) AS \"visible!\"",
+        id.to_string(),
+        caller.to_string()
+    )
+    .fetch_one(app_state.pool())
+    .await?;
+
+    match visible {
+        true => Ok(()),
+        false => Err(Error::NotFound),
+    }
+}
+
+/// Finds a user the caller is allowed to see, by exact id or handle. Unknown,
+/// undiscoverable and blocking users are indistinguishable to the caller.
+async fn find_visible(
+    app_state: &Appstate,
+    srv: &Addr<Server>,
+    caller: Uuid,
+    by_id: Option<String>,
+    by_handle: Option<String>,
+) -> Result<PublicUser> {
+    let user = sqlx::query!(
+        "SELECT c.id, c.handle, c.discoverable, (k.contact_id IS NOT NULL) AS \"is_contact!\",
+            p.display_name AS \"display_name?\", p.bio AS \"bio?\",
+            p.avatar_version AS \"avatar_version?\", p.updated_at AS \"updated_at?\"
+        FROM clients c
+        LEFT JOIN contacts k ON k.client_id = $3 AND k.contact_id = c.id
+        LEFT JOIN profiles p ON p.client_id = c.id
+        WHERE (c.id = $1 OR c.handle = $2)
+            AND NOT EXISTS (SELECT 1 FROM blocks b WHERE b.client_id = c.id AND b.blocked_id = $3)",
+        by_id,
+        by_handle,
+        caller.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .filter(|user| user.discoverable || user.is_contact)
+    .ok_or(Error::NotFound)?;
+
+    let id = Uuid::parse_str(&user.id).map_err(|_| Error::NotFound)?;
+    let online = match user.is_contact {
+        true => Some(srv.send(GetOnline { ids: vec![id] }).await?.contains(&id)),
+        false => None,
+    };
+
+    Ok(PublicUser {
+        id: user.id,
+        handle: user.handle,
+        profile: Profile {
+            display_name: user.display_name,
+            bio: user.bio,
+            avatar_url: avatar_url(&id, user.avatar_version.as_deref()),
+            updated_at: user.updated_at,
+        },
+        is_contact: user.is_contact,
+        online,
+    })
+}
+
+#[actix_web::get("/api/users/lookup")]
+pub async fn lookup(
+    auth: Authenticated<scopes::Contacts>,
+    req: HttpRequest,
+    query: web::Query<LookupQuery>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let caller = auth.id();
+    limit_lookup(&app_state, &req, caller)?;
+
+    let (by_id, by_handle) = match Uuid::parse_str(query.q.trim()) {
+        Ok(id) => (Some(id.to_string()), None),
+        Err(_) => (None, Some(normalize_handle(&query.q)?)),
+    };
+
+    let user = find_visible(&app_state, &srv, caller, by_id, by_handle).await?;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[actix_web::get("/api/users/{id}/profile")]
+pub async fn public_profile(
+    auth: Authenticated<scopes::Contacts>,
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let caller = auth.id();
+
+    let user = find_visible(&app_state, &srv, caller, Some(id.to_string()), None).await;
+
+    // Anything but a contact's profile is as good as a lookup, so it counts
+    // against the same limit.
+    if !user.as_ref().is_ok_and(|user| user.is_contact) {
+        limit_lookup(&app_state, &req, caller)?;
+    }
+
+    Ok(HttpResponse::Ok().json(user?))
+}
+
+#[actix_web::patch("/api/self/settings")]
+pub async fn update_settings(
+    auth: Authenticated<scopes::Profile>,
+    payload: web::Json<SettingsPayload>,
+    app_state: web::Data<Arc<Appstate>>,
+    srv: web::Data<Addr<Server>>,
+) -> Result<HttpResponse> {
+    let id = auth.id();
+    let handle = payload
+        .handle
+        .as_deref()
+        .map(normalize_handle)
+        .transpose()?;
+
+    let settings = sqlx::query!(
+        "UPDATE clients SET
+            contacts_only = COALESCE($2, contacts_only),
+            discoverable = COALESCE($3, discoverable),
+            handle = COALESCE($4, handle)
+        WHERE id = $1
+        RETURNING contacts_only, discoverable, handle",
+        id.to_string(),
+        payload.contacts_only,
+        payload.discoverable,
+        handle
+    )
+    .fetch_one(app_state.pool())
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref db) if db.is_unique_violation() => {
+            Error::Conflict("Handle is already taken".into())
+        }
+        e => e.into(),
+    })?;
+
+    if payload.contacts_only.is_some() {
+        srv.enqueue(SetContactsOnly {
+            id,
+            contacts_only: settings.contacts_only,
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "contacts_only": settings.contacts_only,
+        "discoverable": settings.discoverable,
+        "handle": settings.handle,
+    })))
+}
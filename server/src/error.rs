@@ -1,4 +1,17 @@
-use actix_web::{HttpResponse, ResponseError, error::HttpError, http::StatusCode};
+//! The crate's error type. Every error reaches clients in the same envelope,
+//! `{code, message, request_id, details}`, where `code` is stable and meant
+//! for programs. What went wrong internally is only logged.
+
+use actix_web::{
+    HttpMessage, HttpResponse, ResponseError,
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{StatusCode, header},
+    middleware::Next,
+};
+use serde::Serialize;
+use serde_json::json;
+use tracing_actix_web::RequestId;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -22,7 +35,7 @@ pub enum Error {
     #[error(transparent)]
     MailBox(#[from] actix::MailboxError),
 
-    #[error("something unexpected happen with the database {}", 0.to_string())]
+    #[error("database error: {0}")]
     Database(#[from] sqlx::Error),
 
     #[error("Oops some environment variable is missing")]
@@ -33,40 +46,313 @@ pub enum Error {
 
     #[error("Unauthorized")]
     Unauthorized,
+
+    #[error("Forbidden")]
+    Forbidden,
+
+    #[error("Not found")]
+    NotFound,
+
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    #[error("Upload is missing chunks")]
+    IncompleteUpload { missing_chunks: Vec<u32> },
+
+    #[error("Too many requests")]
+    TooManyRequests,
+
+    #[error("Unsupported or corrupt image")]
+    Image(#[from] image::ImageError),
+
+    #[error(transparent)]
+    Uuid(#[from] uuid::Error),
+
+    #[error(transparent)]
+    Metrics(#[from] prometheus::Error),
 }
 
-impl ResponseError for Error {
-    fn status_code(&self) -> actix_web::http::StatusCode {
+impl Error {
+    /// Name of the variant, used to label metrics.
+    pub fn variant(&self) -> &'static str {
         match self {
-            Error::Io(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Error::Json(_) => actix_web::http::StatusCode::BAD_REQUEST,
-            Error::Jwt(_) => actix_web::http::StatusCode::UNAUTHORIZED,
-            Error::ActixWeb(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Error::WebsocketServer(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Error::MailBox(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Error::Database(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Error::Enviroment(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Error::UlidGeneration(monotonic_error) => {
-                actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+            Error::Io(_) => "Io",
+            Error::Json(_) => "Json",
+            Error::Jwt(_) => "Jwt",
+            Error::ActixWeb(_) => "ActixWeb",
+            Error::WebsocketServer(_) => "WebsocketServer",
+            Error::MailBox(_) => "MailBox",
+            Error::Database(_) => "Database",
+            Error::Enviroment(_) => "Enviroment",
+            Error::UlidGeneration(_) => "UlidGeneration",
+            Error::Unauthorized => "Unauthorized",
+            Error::Forbidden => "Forbidden",
+            Error::NotFound => "NotFound",
+            Error::BadRequest(_) => "BadRequest",
+            Error::Conflict(_) => "Conflict",
+            Error::IncompleteUpload { .. } => "IncompleteUpload",
+            Error::TooManyRequests => "TooManyRequests",
+            Error::Image(_) => "Image",
+            Error::Uuid(_) => "Uuid",
+            Error::Metrics(_) => "Metrics",
+        }
+    }
+}
+
+/// The body of every error response.
+#[derive(Serialize, Debug)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    pub request_id: Option<String>,
+    pub details: Option<serde_json::Value>,
+}
+
+/// Code for errors that only carry a status, e.g. those of actix's
+/// extractors.
+fn status_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        status if status.is_client_error() => "bad_request",
+        _ => "internal",
+    }
+}
+
+const INTERNAL_MESSAGE: &str = "Internal server error";
+
+impl Error {
+    /// Stable, machine-readable code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Json(_) => "invalid_json",
+            Error::Jwt(_) => "invalid_token",
+            Error::Unauthorized => "unauthorized",
+            Error::Forbidden => "forbidden",
+            Error::NotFound => "not_found",
+            Error::BadRequest(_) => "bad_request",
+            Error::Conflict(_) => "conflict",
+            Error::IncompleteUpload { .. } => "upload_incomplete",
+            Error::TooManyRequests => "rate_limited",
+            Error::Image(_) => "invalid_image",
+            error => status_code(error.status_code()),
+        }
+    }
+
+    /// What the client is told. Server errors get a generic message, their
+    /// cause is only logged.
+    pub fn message(&self) -> String {
+        match self {
+            Error::Json(_) => "Invalid JSON".into(),
+            Error::Jwt(_) => "Invalid or expired token".into(),
+            Error::ActixWeb(e) if e.error_response().status().is_client_error() => e.to_string(),
+            Error::Unauthorized
+            | Error::Forbidden
+            | Error::NotFound
+            | Error::BadRequest(_)
+            | Error::Conflict(_)
+            | Error::IncompleteUpload { .. }
+            | Error::TooManyRequests
+            | Error::Image(_) => self.to_string(),
+            _ => INTERNAL_MESSAGE.into(),
+        }
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Error::Json(e) => Some(json!({"reason": e.to_string()})),
+            Error::IncompleteUpload { missing_chunks } => {
+                Some(json!({"missing_chunks": missing_chunks}))
             }
-            Error::Unauthorized => actix_web::http::StatusCode::UNAUTHORIZED,
+            _ => None,
         }
     }
 
-    fn error_response(&self) -> HttpResponse {
+    pub fn body(&self, request_id: Option<String>) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.message(),
+            request_id,
+            details: self.details(),
+        }
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
         match self {
-            Error::Io(e) => HttpResponse::InternalServerError().json(e.to_string()),
-            Error::Json(e) => HttpResponse::BadRequest().json(e.to_string()),
-            Error::Jwt(_) => HttpResponse::Unauthorized().finish(),
-            Error::ActixWeb(e) => HttpResponse::InternalServerError().json(e.to_string()),
-            Error::WebsocketServer(e) => HttpResponse::InternalServerError().json(e.to_string()),
-            Error::MailBox(mailbox_error) => {
-                HttpResponse::InternalServerError().json(mailbox_error.to_string())
+            Error::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Json(_) => StatusCode::BAD_REQUEST,
+            Error::Jwt(_) => StatusCode::UNAUTHORIZED,
+            Error::ActixWeb(e) => e.error_response().status(),
+            Error::WebsocketServer(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::MailBox(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Enviroment(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::UlidGeneration(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Unauthorized => StatusCode::UNAUTHORIZED,
+            Error::Forbidden => StatusCode::FORBIDDEN,
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::IncompleteUpload { .. } => StatusCode::CONFLICT,
+            Error::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            Error::Image(_) => StatusCode::BAD_REQUEST,
+            Error::Uuid(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Metrics(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+
+        if status.is_server_error() {
+            tracing::error!(error = %self, "Request failed");
+        }
+
+        HttpResponse::build(status).json(self.body(None))
+    }
+}
+
+/// Puts every error response, including those of actix itself, in the
+/// envelope and adds the request id. Runs inside `TracingLogger`, which
+/// assigns the id.
+pub async fn envelope(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> std::result::Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let request_id = req.extensions().get::<RequestId>().map(ToString::to_string);
+    let res = next.call(req).await?;
+
+    let Some(error) = res.response().error() else {
+        return Ok(res.map_into_boxed_body());
+    };
+
+    let body = match error.as_error::<Error>() {
+        Some(error) => error.body(request_id),
+        None => {
+            let status = res.status();
+            ErrorBody {
+                code: status_code(status),
+                message: match status.is_client_error() {
+                    true => error.to_string(),
+                    false => INTERNAL_MESSAGE.into(),
+                },
+                request_id,
+                details: None,
             }
-            Error::Database(error) => HttpResponse::InternalServerError().json(error.to_string()),
-            Error::Enviroment(_) => HttpResponse::InternalServerError().finish(),
-            Error::UlidGeneration(_) => HttpResponse::InternalServerError().finish(),
-            Error::Unauthorized => HttpResponse::Unauthorized().finish(),
         }
+    };
+    let body = serde_json::to_string(&body).unwrap_or_default();
+
+    // Mapping the body keeps the error on the response for `TracingLogger`.
+    Ok(res.map_body(|head, _| {
+        head.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/json"),
+        );
+        BoxBody::new(body)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_errors_have_their_own_codes() {
+        let cases = [
+            (
+                Error::Unauthorized,
+                "unauthorized",
+                StatusCode::UNAUTHORIZED,
+            ),
+            (Error::Forbidden, "forbidden", StatusCode::FORBIDDEN),
+            (Error::NotFound, "not_found", StatusCode::NOT_FOUND),
+            (
+                Error::BadRequest("x".into()),
+                "bad_request",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                Error::Conflict("x".into()),
+                "conflict",
+                StatusCode::CONFLICT,
+            ),
+            (
+                Error::IncompleteUpload {
+                    missing_chunks: vec![1],
+                },
+                "upload_incomplete",
+                StatusCode::CONFLICT,
+            ),
+            (
+                Error::TooManyRequests,
+                "rate_limited",
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                Error::Jwt(jsonwebtoken::errors::ErrorKind::ExpiredSignature.into()),
+                "invalid_token",
+                StatusCode::UNAUTHORIZED,
+            ),
+        ];
+
+        for (error, code, status) in cases {
+            assert_eq!(error.code(), code, "{error}");
+            assert_eq!(error.status_code(), status, "{error}");
+        }
+    }
+
+    #[test]
+    fn actix_errors_are_coded_by_status() {
+        let error = Error::ActixWeb(actix_web::error::ErrorMethodNotAllowed("nope"));
+        assert_eq!(error.code(), "method_not_allowed");
+        assert_eq!(error.message(), "nope");
+
+        let error = Error::ActixWeb(actix_web::error::ErrorImATeapot("tea"));
+        assert_eq!(error.code(), "bad_request");
+
+        let error = Error::ActixWeb(actix_web::error::ErrorBadGateway("secret"));
+        assert_eq!(error.code(), "internal");
+        assert_eq!(error.message(), INTERNAL_MESSAGE);
+    }
+
+    #[test]
+    fn internal_errors_hide_their_cause() {
+        let error = Error::Io(std::io::Error::other("/var/lib/secret"));
+
+        assert_eq!(error.code(), "internal");
+        assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.message(), INTERNAL_MESSAGE);
+        assert!(error.details().is_none());
+    }
+
+    #[test]
+    fn body_carries_details_and_request_id() {
+        let body = Error::IncompleteUpload {
+            missing_chunks: vec![2, 5],
+        }
+        .body(Some("abc".into()));
+
+        assert_eq!(
+            serde_json::to_value(body).unwrap(),
+            json!({
+                "code": "upload_incomplete",
+                "message": Error::IncompleteUpload { missing_chunks: vec![2, 5] }.to_string(),
+                "request_id": "abc",
+                "details": {"missing_chunks": [2, 5]},
+            })
+        );
     }
 }
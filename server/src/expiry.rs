@@ -0,0 +1,153 @@
+//! Deletes messages once their disappearing timer ran out, together with their
+//! attachments, and tells both participants which messages are gone. Uploads
+//! that were never sent are dropped as well once they are a day old.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use actix::Addr;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    attachments,
+    error::Result,
+    types::Appstate,
+    websocket::{
+        actions::Notify,
+        event::{ServerEvent, ServerEventType},
+        server::{Enqueue, Server},
+    },
+};
+
+const REAP_INTERVAL: Duration = Duration::from_secs(5);
+const BATCH_SIZE: i64 = 500;
+const UPLOAD_REAP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const UPLOAD_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub fn spawn(app_state: Arc<Appstate>, srv: Addr<Server>) {
+    let uploads = app_state.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(UPLOAD_REAP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = reap_uploads(&uploads).await {
+                tracing::error!(error = %e, "Failed to reap abandoned uploads");
+            }
+        }
+    });
+
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(REAP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = reap(&app_state, &srv).await {
+                tracing::error!(error = %e, "Failed to reap expired messages");
+            }
+        }
+    });
+}
+
+async fn reap(app_state: &Appstate, srv: &Addr<Server>) -> Result<()> {
+    loop {
+        let mut tx = app_state.pool().begin().await?;
+
+        let ids = sqlx::query_scalar!(
+            "SELECT id FROM messages WHERE expires_at <= NOW()
+            ORDER BY expires_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED",
+            BATCH_SIZE
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let hashes = sqlx::query_scalar!(
+            "SELECT DISTINCT sha256 FROM attachments WHERE message_id = ANY($1)",
+            &ids
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let deleted = sqlx::query!(
+            "DELETE FROM messages WHERE id = ANY($1) RETURNING id, sender_id, recipient_id, root_id",
+            &ids
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        // Roots that outlive some of their replies keep an accurate count.
+        let roots: Vec<String> = deleted.iter().filter_map(|m| m.root_id.clone()).collect();
+        sqlx::query!(
+            "UPDATE messages m SET reply_count = GREATEST(m.reply_count - r.n, 0)
+            FROM (SELECT root_id, COUNT(*)::int AS n FROM UNNEST($1::text[]) AS root_id GROUP BY root_id) r
+            WHERE m.id = r.root_id",
+            &roots
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        attachments::remove_unreferenced(app_state, &hashes).await?;
+
+        let mut conversations: HashMap<(Uuid, Uuid), Vec<String>> = HashMap::new();
+        for message in deleted {
+            let from: Uuid = message.sender_id.parse()?;
+            let to: Uuid = message.recipient_id.parse()?;
+
+            conversations
+                .entry((from.min(to), from.max(to)))
+                .or_default()
+                .push(message.id);
+        }
+
+        // Sent regardless of blocks, so neither side keeps a copy around.
+        for ((a, b), ids) in conversations {
+            let event = ServerEvent::new(
+                ServerEventType::MessagesExpired,
+                serde_json::json!({"ids": ids}),
+            );
+
+            srv.enqueue(Notify {
+                to: a,
+                event: event.clone(),
+            });
+            srv.enqueue(Notify { to: b, event });
+        }
+
+        if (ids.len() as i64) < BATCH_SIZE {
+            return Ok(());
+        }
+    }
+}
+
+/// Drops uploads, finished or not, that were not attached to a message in
+/// time, along with their staged chunks and any content nothing else uses.
+async fn reap_uploads(app_state: &Appstate) -> Result<()> {
+    let expired = sqlx::query!(
+        "DELETE FROM attachments WHERE message_id IS NULL AND created_at <= $1
+        RETURNING id, sha256",
+        Utc::now() - UPLOAD_TTL
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    if expired.is_empty() {
+        return Ok(());
+    }
+
+    for upload in &expired {
+        attachments::discard_staging(app_state, &upload.id).await?;
+    }
+
+    let hashes: Vec<String> = expired.into_iter().map(|upload| upload.sha256).collect();
+    attachments::remove_unreferenced(app_state, &hashes).await
+}
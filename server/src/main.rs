@@ -1,19 +1,34 @@
+pub mod access;
+pub mod attachments;
+pub mod auth;
 pub mod endpoints;
 pub mod error;
+pub mod expiry;
+pub mod messages;
+pub mod metrics;
+pub mod reactions;
+pub mod shutdown;
+pub mod thumbnails;
 pub mod types;
 pub mod utils;
 pub mod websocket;
-use std::{collections::HashMap, env, rc::Rc, sync::Arc};
+use std::{
+    env,
+    sync::{Arc, atomic::Ordering},
+};
 
 use crate::{
-    error::{Error, Result},
+    access::AccessList,
+    auth::{Authenticated, scopes},
+    error::Result,
     types::{Appstate, TokenManager},
-    websocket::WsClient,
+    websocket::{WsClient, load_session},
 };
 use actix::{Actor, Addr};
-use actix_web::{App, HttpRequest, HttpServer, Responder, web};
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
 use actix_web_actors::ws;
 use sqlx::postgres::PgPoolOptions;
+use tracing_actix_web::TracingLogger;
 use uuid::Uuid;
 
 pub async fn ws_index(
@@ -21,45 +36,67 @@ pub async fn ws_index(
     stream: web::Payload,
     srv: web::Data<Addr<crate::websocket::server::Server>>,
     app_state: web::Data<Arc<Appstate>>,
-) -> crate::error::Result<impl Responder> {
-    let token = req
-        .headers()
-        .get("Authorization")
-        .and_then(|hv| hv.to_str().ok())
-        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing Authorization header"))?;
-    let claims = app_state.token_manager.validate_token(&token)?;
-    println!("client tried to reconnect with id {}", claims.id);
-
-    if sqlx::query!(
-        "SELECT id FROM clients WHERE id = $1",
-        claims.id.to_string()
-    )
-    .fetch_optional(app_state.pool())
-    .await?
-    .is_none()
-    {
-        println!("not found in databae");
-        return Err(Error::Unauthorized);
+) -> crate::error::Result<HttpResponse> {
+    if app_state.shutting_down.load(Ordering::Relaxed) {
+        return Ok(HttpResponse::ServiceUnavailable()
+            .insert_header((
+                actix_web::http::header::RETRY_AFTER,
+                crate::shutdown::RETRY_AFTER.as_secs(),
+            ))
+            .finish());
     }
 
-    let ws = WsClient::new(
-        claims.id,
+    // A token in the handshake must be valid. Without one the socket has to
+    // authenticate with its first event instead.
+    let auth = match auth::header_token(&req).or_else(|| auth::subprotocol_token(&req)) {
+        Some(token) => Some(Authenticated::<scopes::Messages>::from_token(
+            &app_state, token,
+        )?),
+        None => None,
+    };
+
+    let connection_id = Uuid::new_v4();
+    let mut ws = WsClient::new(
         srv.get_ref().clone(),
         app_state.get_ref().clone(),
+        connection_id,
+        req.connection_info().realip_remote_addr().map(String::from),
     );
 
-    Ok(ws::start(ws, &req, stream)?)
+    match auth {
+        Some(auth) => {
+            tracing::info!(client_id = %auth.id(), %connection_id, "Upgrading to websocket");
+            ws = ws.authenticated(&auth.claims, load_session(&app_state, auth.id()).await?);
+        }
+        None => tracing::info!(%connection_id, "Upgrading unauthenticated websocket"),
+    }
+
+    Ok(ws::WsResponseBuilder::new(ws, &req, stream)
+        .protocols(&[auth::BEARER_PROTOCOL])
+        .start()?)
 }
 
 #[actix_web::main]
 async fn main() -> Result<()> {
     dotenvy::dotenv().ok();
-    tracing_subscriber::fmt::init();
+    crate::utils::logging::init(
+        env::var("LOG_FILTER").ok().as_deref(),
+        env::var("LOG_FORMAT").ok().as_deref(),
+    );
 
     let pg_username = env::var("PG_USERNAME")?;
     let db_passwd = env::var("DB_PASSWD")?;
     let db_name = env::var("DB_NAME")?;
     let jwt_secret = env::var("JWT_SECRET")?;
+    let data_dir = env::var("DATA_DIR").unwrap_or_else(|_| String::from("data"));
+    let edit_window = env::var("MESSAGE_EDIT_WINDOW_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(std::time::Duration::from_secs);
+    let shutdown_grace = env::var("SHUTDOWN_GRACE_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(crate::shutdown::DEFAULT_GRACE_SECS);
 
     let token_manager = TokenManager::new(jwt_secret);
     let ws_server = crate::websocket::server::Server::start_default();
@@ -71,19 +108,89 @@ async fn main() -> Result<()> {
         ))
         .await?;
 
-    let app_state = Arc::new(Appstate::new(pool, token_manager));
+    let access = AccessList::load(&pool).await?;
+    let app_state = Arc::new(Appstate::new(
+        pool,
+        token_manager,
+        data_dir.into(),
+        edit_window,
+        access,
+        env::var("ADMIN_KEY").ok().filter(|key| !key.is_empty()),
+    ));
+    crate::thumbnails::resume(app_state.clone(), ws_server.clone()).await?;
+    crate::expiry::spawn(app_state.clone(), ws_server.clone());
 
-    HttpServer::new(move || {
+    let shutdown_state = app_state.clone();
+    let shutdown_srv = ws_server.clone();
+
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(ws_server.clone()))
             .app_data(web::Data::new(app_state.clone()))
+            // Request spans carry a request id but never headers, so tokens
+            // stay out of the logs.
+            .wrap(actix_web::middleware::from_fn(crate::error::envelope))
+            .wrap(TracingLogger::default())
             .route("/ws", web::get().to(ws_index))
+            .service(crate::endpoints::metrics::metrics)
+            .service(crate::endpoints::health::healthz)
+            .service(crate::endpoints::health::readyz)
             .service(crate::endpoints::authenticate)
             .service(crate::endpoints::change_id)
+            .service(crate::endpoints::refresh)
+            .service(crate::endpoints::recover)
+            .service(crate::endpoints::reissue_recovery_key)
+            .service(crate::endpoints::contacts::send_request)
+            .service(crate::endpoints::contacts::list_requests)
+            .service(crate::endpoints::contacts::accept_request)
+            .service(crate::endpoints::contacts::decline_request)
+            .service(crate::endpoints::contacts::cancel_request)
+            .service(crate::endpoints::contacts::list_contacts)
+            .service(crate::endpoints::contacts::remove_contact)
+            .service(crate::endpoints::users::lookup)
+            .service(crate::endpoints::users::public_profile)
+            .service(crate::endpoints::users::update_settings)
+            .service(crate::endpoints::profiles::get_profile)
+            .service(crate::endpoints::profiles::update_profile)
+            .service(crate::endpoints::avatars::get_avatar)
+            .service(crate::endpoints::avatars::upload_avatar)
+            .service(crate::endpoints::avatars::delete_avatar)
+            .service(crate::endpoints::blocks::block)
+            .service(crate::endpoints::blocks::unblock)
+            .service(crate::endpoints::blocks::list_blocked)
+            .service(crate::endpoints::conversations::mute)
+            .service(crate::endpoints::conversations::unmute)
+            .service(crate::endpoints::conversations::history)
+            .service(crate::endpoints::conversations::get_disappearing)
+            .service(crate::endpoints::conversations::set_disappearing)
+            .service(crate::endpoints::messages::edits)
+            .service(crate::endpoints::messages::thread)
+            .service(crate::endpoints::search::search)
+            .service(crate::endpoints::attachments::create_upload)
+            .service(crate::endpoints::attachments::get_attachment)
+            .service(crate::endpoints::attachments::upload_chunk)
+            .service(crate::endpoints::attachments::finalize_upload)
+            .service(crate::endpoints::attachments::get_content)
+            .service(crate::endpoints::attachments::get_thumbnail)
+            .service(crate::endpoints::admin::list_sessions)
+            .service(crate::endpoints::admin::kick)
+            .service(crate::endpoints::admin::ban)
+            .service(crate::endpoints::admin::unban)
+            .service(crate::endpoints::admin::revoke_tokens)
+            .service(crate::endpoints::admin::stats)
+            .service(crate::endpoints::admin::audit_log)
+            .default_service(web::to(|| async {
+                Err::<HttpResponse, _>(crate::error::Error::NotFound)
+            }))
     })
     .bind(("127.0.0.1", 8080))?
-    .run()
-    .await?;
+    // Signals are handled by `shutdown` so sessions can be drained first.
+    .disable_signals()
+    .shutdown_timeout(shutdown_grace)
+    .run();
+
+    crate::shutdown::spawn(shutdown_state, shutdown_srv, server.handle());
+    server.await?;
 
     Ok(())
 }
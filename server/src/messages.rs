@@ -0,0 +1,697 @@
+//! Persistence for chat messages, shared by the websocket session that sends
+//! them and the endpoints that read them back.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    attachments,
+    error::{Error, Result},
+    reactions::{self, ReactionCount},
+    types::Appstate,
+};
+
+pub const MAX_CONTENT_LENGTH: usize = 4000;
+const SNIPPET_LENGTH: i32 = 140;
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachmentRef {
+    pub id: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: i64,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub blurhash: Option<String>,
+    pub has_thumbnail: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Message {
+    pub id: String,
+    pub from: Uuid,
+    pub to: Uuid,
+    pub content: String,
+    pub sent_at: DateTime<Utc>,
+    pub attachments: Vec<AttachmentRef>,
+    pub edited_at: Option<DateTime<Utc>>,
+    /// Deleted messages are kept as tombstones without content or attachments.
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Only loaded for history, since `reacted` depends on who is asking.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reactions: Option<Vec<ReactionCount>>,
+    /// The message this one replies to.
+    pub reply_to: Option<Quote>,
+    /// The first message of the thread this one is part of.
+    pub root_id: Option<String>,
+    /// Only counted on thread roots.
+    pub reply_count: i32,
+    pub last_reply_at: Option<DateTime<Utc>>,
+    /// Set on messages sent while the conversation had disappearing messages
+    /// on; they are deleted for both sides at this time.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// The start of a replied-to message, enough to render it as a quote.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Quote {
+    pub id: String,
+    pub from: Uuid,
+    pub snippet: String,
+    pub deleted: bool,
+}
+
+/// The new state of a thread root after a reply was added to it.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadUpdate {
+    pub root_id: String,
+    #[serde(skip)]
+    pub root_author: Uuid,
+    pub reply_count: i32,
+    pub last_reply_at: DateTime<Utc>,
+}
+
+struct MessageRow {
+    id: String,
+    sender_id: String,
+    recipient_id: String,
+    content: String,
+    created_at: DateTime<Utc>,
+    edited_at: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+    parent_id: Option<String>,
+    root_id: Option<String>,
+    reply_count: i32,
+    last_reply_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct Edit {
+    pub content: String,
+    pub replaced_at: DateTime<Utc>,
+}
+
+/// Checks that `from` may send to `to`: the recipient exists, neither side has
+/// blocked the other, and the recipient either accepts strangers or has
+/// `from` as a contact.
+pub async fn authorize(app_state: &Appstate, from: Uuid, to: Uuid) -> Result<()> {
+    let allowed = sqlx::query_scalar!(
+        "SELECT (NOT c.contacts_only OR EXISTS (
+                SELECT 1 FROM contacts WHERE client_id = c.id AND contact_id = $2
+            ))
+            AND NOT EXISTS (
+                SELECT 1 FROM blocks
+                WHERE (client_id = c.id AND blocked_id = $2) OR (client_id = $2 AND blocked_id = c.id)
+            ) AS \"allowed!\"
+        FROM clients c
+        WHERE c.id = $1",
+        to.to_string(),
+        from.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?;
+
+    match allowed {
+        Some(true) => Ok(()),
+        _ => Err(Error::Forbidden),
+    }
+}
+
+/// Stores a message and claims the sender's finished, unclaimed attachments
+/// for it. Replies also bump their thread root, whose new state is returned
+/// alongside.
+pub async fn store(
+    app_state: &Appstate,
+    id: Ulid,
+    from: Uuid,
+    to: Uuid,
+    content: String,
+    attachments: &[String],
+    reply_to: Option<&str>,
+) -> Result<(Message, Option<ThreadUpdate>)> {
+    if content.chars().count() > MAX_CONTENT_LENGTH {
+        return Err(Error::BadRequest(format!(
+            "Messages are limited to {MAX_CONTENT_LENGTH} characters"
+        )));
+    }
+
+    if content.trim().is_empty() && attachments.is_empty() {
+        return Err(Error::BadRequest("Message is empty".into()));
+    }
+
+    let mut tx = app_state.pool().begin().await?;
+
+    let parent = match reply_to {
+        Some(parent_id) => Some(
+            sqlx::query!(
+                "SELECT id, sender_id, LEFT(content, $4) AS \"snippet!\", deleted_at,
+                    COALESCE(root_id, id) AS \"root_id!\"
+                FROM messages
+                WHERE id = $1
+                    AND LEAST(sender_id, recipient_id) = LEAST($2, $3)
+                    AND GREATEST(sender_id, recipient_id) = GREATEST($2, $3)",
+                parent_id,
+                from.to_string(),
+                to.to_string(),
+                SNIPPET_LENGTH
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| {
+                Error::BadRequest("Replies must be to a message in the same conversation".into())
+            })?,
+        ),
+        None => None,
+    };
+
+    if parent.as_ref().is_some_and(|p| p.deleted_at.is_some()) {
+        return Err(Error::BadRequest(
+            "Cannot reply to a deleted message".into(),
+        ));
+    }
+
+    let inserted = sqlx::query!(
+        "INSERT INTO messages (id, sender_id, recipient_id, content, parent_id, root_id, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, (
+            SELECT NOW() + disappear_after * INTERVAL '1 second' FROM conversation_settings
+            WHERE low_id = LEAST($2::varchar, $3::varchar) AND high_id = GREATEST($2::varchar, $3::varchar)
+        ))
+        RETURNING created_at, expires_at",
+        id.to_string(),
+        from.to_string(),
+        to.to_string(),
+        content,
+        parent.as_ref().map(|p| p.id.clone()),
+        parent.as_ref().map(|p| p.root_id.clone())
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let sent_at = inserted.created_at;
+
+    let claimed = sqlx::query_as!(
+        AttachmentRef,
+        "UPDATE attachments SET message_id = $1
+        WHERE id = ANY($2) AND uploader_id = $3 AND message_id IS NULL AND completed_at IS NOT NULL
+        RETURNING id, file_name, content_type, size, width, height, blurhash, has_thumbnail",
+        id.to_string(),
+        attachments,
+        from.to_string()
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if claimed.len() != attachments.len() {
+        return Err(Error::BadRequest(
+            "Attachments must be finished uploads of your own that are not sent yet".into(),
+        ));
+    }
+
+    let thread = match &parent {
+        Some(parent) => {
+            let root = sqlx::query!(
+                "UPDATE messages SET reply_count = reply_count + 1, last_reply_at = $2
+                WHERE id = $1
+                RETURNING sender_id, reply_count, last_reply_at AS \"last_reply_at!\"",
+                parent.root_id,
+                sent_at
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            Some(ThreadUpdate {
+                root_id: parent.root_id.clone(),
+                root_author: root.sender_id.parse()?,
+                reply_count: root.reply_count,
+                last_reply_at: root.last_reply_at,
+            })
+        }
+        None => None,
+    };
+
+    tx.commit().await?;
+
+    let message = Message {
+        id: id.to_string(),
+        from,
+        to,
+        content,
+        sent_at,
+        attachments: claimed,
+        edited_at: None,
+        deleted_at: None,
+        reactions: None,
+        root_id: thread.as_ref().map(|t| t.root_id.clone()),
+        reply_to: match parent {
+            Some(parent) => Some(Quote {
+                from: parent.sender_id.parse()?,
+                id: parent.id,
+                snippet: parent.snippet,
+                deleted: false,
+            }),
+            None => None,
+        },
+        reply_count: 0,
+        last_reply_at: None,
+        expires_at: inserted.expires_at,
+    };
+
+    Ok((message, thread))
+}
+
+async fn attach(app_state: &Appstate, rows: Vec<MessageRow>) -> Result<Vec<Message>> {
+    let ids: Vec<String> = rows.iter().map(|row| row.id.clone()).collect();
+
+    let mut attachments: HashMap<String, Vec<AttachmentRef>> = HashMap::new();
+    for row in sqlx::query!(
+        "SELECT id, message_id AS \"message_id!\", file_name, content_type, size, width, height, blurhash, has_thumbnail
+        FROM attachments
+        WHERE message_id = ANY($1)
+        ORDER BY id",
+        &ids
+    )
+    .fetch_all(app_state.pool())
+    .await?
+    {
+        attachments
+            .entry(row.message_id)
+            .or_default()
+            .push(AttachmentRef {
+                id: row.id,
+                file_name: row.file_name,
+                content_type: row.content_type,
+                size: row.size,
+                width: row.width,
+                height: row.height,
+                blurhash: row.blurhash,
+                has_thumbnail: row.has_thumbnail,
+            });
+    }
+
+    let parent_ids: Vec<String> = rows
+        .iter()
+        .filter_map(|row| row.parent_id.clone())
+        .collect();
+    let mut quotes: HashMap<String, Quote> = HashMap::new();
+    for row in sqlx::query!(
+        "SELECT id, sender_id, LEFT(content, $2) AS \"snippet!\", deleted_at FROM messages
+        WHERE id = ANY($1)",
+        &parent_ids,
+        SNIPPET_LENGTH
+    )
+    .fetch_all(app_state.pool())
+    .await?
+    {
+        quotes.insert(
+            row.id.clone(),
+            Quote {
+                from: row.sender_id.parse()?,
+                id: row.id,
+                snippet: row.snippet,
+                deleted: row.deleted_at.is_some(),
+            },
+        );
+    }
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(Message {
+                reply_to: row.parent_id.and_then(|id| quotes.get(&id).cloned()),
+                attachments: attachments.remove(&row.id).unwrap_or_default(),
+                from: Uuid::parse_str(&row.sender_id)?,
+                to: Uuid::parse_str(&row.recipient_id)?,
+                id: row.id,
+                content: row.content,
+                sent_at: row.created_at,
+                edited_at: row.edited_at,
+                deleted_at: row.deleted_at,
+                reactions: None,
+                root_id: row.root_id,
+                reply_count: row.reply_count,
+                last_reply_at: row.last_reply_at,
+                expires_at: row.expires_at,
+            })
+        })
+        .collect()
+}
+
+/// Messages between `a` and `b`, newest first, strictly older than `before`
+/// when it is given. Reactions are summarized from `a`'s point of view.
+pub async fn history(
+    app_state: &Appstate,
+    a: Uuid,
+    b: Uuid,
+    before: Option<&str>,
+    limit: i64,
+) -> Result<Vec<Message>> {
+    let rows = sqlx::query_as!(
+        MessageRow,
+        "SELECT id, sender_id, recipient_id, content, created_at, edited_at, deleted_at,
+            parent_id, root_id, reply_count, last_reply_at, expires_at
+        FROM messages
+        WHERE LEAST(sender_id, recipient_id) = LEAST($1, $2)
+            AND GREATEST(sender_id, recipient_id) = GREATEST($1, $2)
+            AND ($3::text IS NULL OR id < $3)
+            AND (expires_at IS NULL OR expires_at > NOW())
+        ORDER BY id DESC
+        LIMIT $4",
+        a.to_string(),
+        b.to_string(),
+        before,
+        limit
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    with_reactions(app_state, a, attach(app_state, rows).await?).await
+}
+
+async fn with_reactions(
+    app_state: &Appstate,
+    viewer: Uuid,
+    mut messages: Vec<Message>,
+) -> Result<Vec<Message>> {
+    let ids: Vec<String> = messages.iter().map(|m| m.id.clone()).collect();
+    let mut reactions = reactions::summaries(app_state, viewer, &ids).await?;
+
+    for message in &mut messages {
+        message.reactions = Some(reactions.remove(&message.id).unwrap_or_default());
+    }
+
+    Ok(messages)
+}
+
+/// The root of the thread `id` belongs to, followed by its replies oldest
+/// first, strictly newer than `after` when it is given.
+pub async fn thread(
+    app_state: &Appstate,
+    viewer: Uuid,
+    id: &str,
+    after: Option<&str>,
+    limit: i64,
+) -> Result<(Message, Vec<Message>)> {
+    let root_id = sqlx::query_scalar!(
+        "SELECT COALESCE(root_id, id) AS \"root_id!\" FROM messages
+        WHERE id = $1 AND (sender_id = $2 OR recipient_id = $2)",
+        id,
+        viewer.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let mut rows = sqlx::query_as!(
+        MessageRow,
+        "SELECT id, sender_id, recipient_id, content, created_at, edited_at, deleted_at,
+            parent_id, root_id, reply_count, last_reply_at, expires_at
+        FROM messages
+        WHERE id = $1",
+        root_id
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    rows.extend(
+        sqlx::query_as!(
+            MessageRow,
+            "SELECT id, sender_id, recipient_id, content, created_at, edited_at, deleted_at,
+                parent_id, root_id, reply_count, last_reply_at, expires_at
+            FROM messages
+            WHERE root_id = $1 AND ($2::text IS NULL OR id > $2)
+                AND (expires_at IS NULL OR expires_at > NOW())
+            ORDER BY id
+            LIMIT $3",
+            root_id,
+            after,
+            limit
+        )
+        .fetch_all(app_state.pool())
+        .await?,
+    );
+
+    let mut messages = with_reactions(app_state, viewer, attach(app_state, rows).await?)
+        .await?
+        .into_iter();
+    let root = messages.next().ok_or(Error::NotFound)?;
+
+    Ok((root, messages.collect()))
+}
+
+/// Optional restrictions on a full-text search.
+#[derive(Default)]
+pub struct SearchFilters<'a> {
+    /// Only the conversation with this peer.
+    pub peer: Option<Uuid>,
+    pub sender: Option<Uuid>,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+    pub has_attachment: Option<bool>,
+    /// Id of the last result already loaded.
+    pub cursor: Option<&'a str>,
+}
+
+/// Wrapped around each match in search snippets.
+pub const HIGHLIGHT_START: char = '\u{2}';
+pub const HIGHLIGHT_END: char = '\u{3}';
+
+/// Messages `viewer` can see that match `query`, newest first, each paired with
+/// a snippet whose matches are wrapped in `HIGHLIGHT_START` and `HIGHLIGHT_END`.
+pub async fn search(
+    app_state: &Appstate,
+    viewer: Uuid,
+    query: &str,
+    filters: &SearchFilters<'_>,
+    limit: i64,
+) -> Result<Vec<(Message, String)>> {
+    let hits = sqlx::query!(
+        "SELECT m.id, ts_headline(
+                'simple',
+                translate(m.content, chr(2) || chr(3), ''),
+                q,
+                'StartSel=' || chr(2) || ', StopSel=' || chr(3) || ', MinWords=5, MaxWords=20'
+            ) AS \"snippet!\"
+        FROM messages m, websearch_to_tsquery('simple', $2) q
+        WHERE m.search @@ q
+            AND (m.sender_id = $1 OR m.recipient_id = $1)
+            AND m.deleted_at IS NULL
+            AND (m.expires_at IS NULL OR m.expires_at > NOW())
+            AND ($3::text IS NULL OR m.sender_id = $3 OR m.recipient_id = $3)
+            AND ($4::text IS NULL OR m.sender_id = $4)
+            AND ($5::timestamptz IS NULL OR m.created_at >= $5)
+            AND ($6::timestamptz IS NULL OR m.created_at < $6)
+            AND ($7::bool IS NULL OR EXISTS (SELECT 1 FROM attachments a WHERE a.message_id = m.id) = $7)
+            AND ($8::text IS NULL OR m.id < $8)
+        ORDER BY m.id DESC
+        LIMIT $9",
+        viewer.to_string(),
+        query,
+        filters.peer.map(|peer| peer.to_string()),
+        filters.sender.map(|sender| sender.to_string()),
+        filters.after,
+        filters.before,
+        filters.has_attachment,
+        filters.cursor,
+        limit
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    let ids: Vec<String> = hits.iter().map(|hit| hit.id.clone()).collect();
+    let mut snippets: HashMap<String, String> =
+        hits.into_iter().map(|hit| (hit.id, hit.snippet)).collect();
+
+    let rows = sqlx::query_as!(
+        MessageRow,
+        "SELECT id, sender_id, recipient_id, content, created_at, edited_at, deleted_at,
+            parent_id, root_id, reply_count, last_reply_at, expires_at
+        FROM messages
+        WHERE id = ANY($1)
+        ORDER BY id DESC",
+        &ids
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    Ok(attach(app_state, rows)
+        .await?
+        .into_iter()
+        .map(|message| {
+            let snippet = snippets.remove(&message.id).unwrap_or_default();
+            (message, snippet)
+        })
+        .collect())
+}
+
+pub async fn load(app_state: &Appstate, id: &str) -> Result<Message> {
+    let row = sqlx::query_as!(
+        MessageRow,
+        "SELECT id, sender_id, recipient_id, content, created_at, edited_at, deleted_at,
+            parent_id, root_id, reply_count, last_reply_at, expires_at
+        FROM messages
+        WHERE id = $1",
+        id
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    attach(app_state, vec![row])
+        .await?
+        .pop()
+        .ok_or(Error::NotFound)
+}
+
+/// Locks a message its author is still allowed to change.
+async fn lock_own(
+    tx: &mut sqlx::PgConnection,
+    app_state: &Appstate,
+    author: Uuid,
+    id: &str,
+) -> Result<String> {
+    let row = sqlx::query!(
+        "SELECT sender_id, content, created_at, deleted_at FROM messages WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    if row.sender_id != author.to_string() {
+        return Err(Error::Forbidden);
+    }
+
+    if row.deleted_at.is_some() {
+        return Err(Error::Conflict("Message was deleted".into()));
+    }
+
+    if Utc::now() - row.created_at
+        > chrono::Duration::from_std(app_state.edit_window).unwrap_or_default()
+    {
+        return Err(Error::BadRequest(format!(
+            "Messages can only be changed within {} seconds of sending",
+            app_state.edit_window.as_secs()
+        )));
+    }
+
+    Ok(row.content)
+}
+
+/// Replaces the content of `author`'s message, keeping the previous version.
+pub async fn edit(
+    app_state: &Appstate,
+    author: Uuid,
+    id: &str,
+    content: String,
+) -> Result<Message> {
+    if content.chars().count() > MAX_CONTENT_LENGTH {
+        return Err(Error::BadRequest(format!(
+            "Messages are limited to {MAX_CONTENT_LENGTH} characters"
+        )));
+    }
+
+    let mut tx = app_state.pool().begin().await?;
+    let previous = lock_own(&mut tx, app_state, author, id).await?;
+
+    if previous == content {
+        return load(app_state, id).await;
+    }
+
+    let has_attachments = sqlx::query_scalar!(
+        "SELECT EXISTS (SELECT 1 FROM attachments WHERE message_id = $1) AS \"exists!\"",
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if content.trim().is_empty() && !has_attachments {
+        return Err(Error::BadRequest(
+            "Message is empty, delete it instead".into(),
+        ));
+    }
+
+    sqlx::query!(
+        "INSERT INTO message_edits (message_id, content) VALUES ($1, $2)",
+        id,
+        previous
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE messages SET content = $2, edited_at = NOW() WHERE id = $1",
+        id,
+        content
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    load(app_state, id).await
+}
+
+/// Turns `author`'s message into a tombstone. Its content, earlier versions,
+/// attachments and reactions are dropped; the row itself stays so history keeps its place.
+pub async fn delete(app_state: &Appstate, author: Uuid, id: &str) -> Result<Message> {
+    let mut tx = app_state.pool().begin().await?;
+    lock_own(&mut tx, app_state, author, id).await?;
+
+    sqlx::query!("DELETE FROM message_edits WHERE message_id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+
+    let hashes = sqlx::query_scalar!(
+        "DELETE FROM attachments WHERE message_id = $1 RETURNING sha256",
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM reactions WHERE message_id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query!(
+        "UPDATE messages SET content = '', deleted_at = NOW() WHERE id = $1",
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    attachments::remove_unreferenced(app_state, &hashes).await?;
+
+    load(app_state, id).await
+}
+
+/// Earlier versions of a message, oldest first.
+pub async fn edits(app_state: &Appstate, id: &str) -> Result<Vec<Edit>> {
+    Ok(sqlx::query_as!(
+        Edit,
+        "SELECT content, replaced_at FROM message_edits WHERE message_id = $1 ORDER BY id",
+        id
+    )
+    .fetch_all(app_state.pool())
+    .await?)
+}
+
+/// Whether `viewer` is one of the two participants of the message.
+pub async fn is_participant(app_state: &Appstate, viewer: Uuid, message_id: &str) -> Result<bool> {
+    Ok(sqlx::query_scalar!(
+        "SELECT id FROM messages WHERE id = $1 AND (sender_id = $2 OR recipient_id = $2)",
+        message_id,
+        viewer.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .is_some())
+}
@@ -0,0 +1,177 @@
+//! Process-wide Prometheus metrics, served in the text exposition format on
+//! `/metrics`.
+
+use std::sync::LazyLock;
+
+use prometheus::{
+    Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder, core::Collector,
+};
+use serde::Serialize;
+
+use crate::{
+    error::{Error, Result},
+    websocket::event::{ClientEventType, ServerEventType},
+};
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+const ROUTING_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+pub struct Metrics {
+    registry: Registry,
+    /// Clients with a live session on the `Server` actor.
+    pub sessions: IntGauge,
+    frames_in: IntCounterVec,
+    frames_out: IntCounterVec,
+    /// From a SEND_MESSAGE frame arriving to the message being handed to the
+    /// recipient's session.
+    pub routing_latency: Histogram,
+    pub heartbeat_timeouts: IntCounter,
+    /// Round-trip time of websocket heartbeats.
+    pub rtt: Histogram,
+    auth_failures: IntCounterVec,
+    /// Messages sent to the `Server` actor that it has not handled yet.
+    pub server_mailbox: IntGauge,
+    db_pool: IntGaugeVec,
+    db_pool_max: IntGauge,
+}
+
+fn register<T: Collector + Clone + 'static>(registry: &Registry, metric: T) -> T {
+    registry
+        .register(Box::new(metric.clone()))
+        .expect("metric names are unique");
+    metric
+}
+
+/// The wire name of an event type, e.g. `SEND_MESSAGE`.
+fn event_label(event_type: &impl Serialize) -> String {
+    serde_json::to_value(event_type)
+        .ok()
+        .and_then(|value| value.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("chat".into()), None)
+            .expect("namespace is a valid metric name");
+
+        Self {
+            sessions: register(
+                &registry,
+                IntGauge::new("ws_sessions", "Connected websocket sessions").unwrap(),
+            ),
+            frames_in: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("ws_frames_in_total", "Websocket events received"),
+                    &["event_type"],
+                )
+                .unwrap(),
+            ),
+            frames_out: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("ws_frames_out_total", "Websocket events sent"),
+                    &["event_type"],
+                )
+                .unwrap(),
+            ),
+            routing_latency: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new(
+                        "message_routing_seconds",
+                        "Time from receiving a message to handing it to the recipient's session",
+                    )
+                    .buckets(ROUTING_BUCKETS.to_vec()),
+                )
+                .unwrap(),
+            ),
+            heartbeat_timeouts: register(
+                &registry,
+                IntCounter::new(
+                    "ws_heartbeat_timeouts_total",
+                    "Websocket sessions closed for missing heartbeats",
+                )
+                .unwrap(),
+            ),
+            rtt: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new("ws_rtt_seconds", "Round-trip time of websocket heartbeats")
+                        .buckets(ROUTING_BUCKETS.to_vec()),
+                )
+                .unwrap(),
+            ),
+            auth_failures: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("auth_failures_total", "Rejected authentication attempts"),
+                    &["error"],
+                )
+                .unwrap(),
+            ),
+            server_mailbox: register(
+                &registry,
+                IntGauge::new(
+                    "server_mailbox_depth",
+                    "Messages waiting in the Server actor's mailbox",
+                )
+                .unwrap(),
+            ),
+            db_pool: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new("db_pool_connections", "Database connections by state"),
+                    &["state"],
+                )
+                .unwrap(),
+            ),
+            db_pool_max: register(
+                &registry,
+                IntGauge::new("db_pool_max_connections", "Database pool size limit").unwrap(),
+            ),
+            registry,
+        }
+    }
+
+    /// Counts an event received from a client. Frames that could not be
+    /// parsed are counted as `INVALID`.
+    pub fn frame_in(&self, event_type: Option<&ClientEventType>) {
+        let label = match event_type {
+            Some(event_type) => event_label(event_type),
+            None => "INVALID".into(),
+        };
+
+        self.frames_in.with_label_values(&[label]).inc();
+    }
+
+    pub fn frame_out(&self, event_type: ServerEventType) {
+        self.frames_out
+            .with_label_values(&[event_label(&event_type)])
+            .inc();
+    }
+
+    pub fn auth_failure(&self, error: &Error) {
+        self.auth_failures
+            .with_label_values(&[error.variant()])
+            .inc();
+    }
+
+    /// Renders every metric, sampling the database pool first.
+    pub fn render(&self, pool: &sqlx::PgPool) -> Result<String> {
+        let size = i64::from(pool.size());
+        let idle = pool.num_idle() as i64;
+
+        self.db_pool.with_label_values(&["idle"]).set(idle);
+        self.db_pool.with_label_values(&["in_use"]).set(size - idle);
+        self.db_pool_max
+            .set(i64::from(pool.options().get_max_connections()));
+
+        Ok(TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+}
@@ -0,0 +1,234 @@
+//! Emoji reactions on messages. A reaction is either a single Unicode emoji or
+//! the shortcode of a known custom emoji.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    endpoints::blocks::blocked_between,
+    error::{Error, Result},
+    types::Appstate,
+};
+
+pub const MAX_REACTIONS_PER_USER: i64 = 5;
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+    /// Whether the client the summary was loaded for is among the reactors.
+    pub reacted: bool,
+}
+
+/// A single emoji as listed by Unicode, including skin tones, ZWJ sequences
+/// and flags. Plain letters, accented or not, are rejected.
+fn is_unicode_emoji(emoji: &str) -> bool {
+    emojis::get(emoji).is_some()
+}
+
+/// Custom emoji are referenced by shortcode, e.g. ":party_parrot:".
+fn is_shortcode(emoji: &str) -> bool {
+    emoji.len() > 2 && emoji.starts_with(':') && emoji.ends_with(':')
+}
+
+async fn validate_emoji(app_state: &Appstate, emoji: &str) -> Result<()> {
+    let valid = emoji.len() <= 64
+        && (is_unicode_emoji(emoji)
+            || (is_shortcode(emoji)
+                && sqlx::query_scalar!("SELECT id FROM custom_emojis WHERE id = $1", emoji)
+                    .fetch_optional(app_state.pool())
+                    .await?
+                    .is_some()));
+
+    match valid {
+        true => Ok(()),
+        false => Err(Error::BadRequest(
+            "Reactions must be a single emoji or a known custom emoji".into(),
+        )),
+    }
+}
+
+/// The other participant of a message `client` may react to.
+async fn peer_of(app_state: &Appstate, client: Uuid, message_id: &str) -> Result<Uuid> {
+    let message = sqlx::query!(
+        "SELECT sender_id, recipient_id, deleted_at FROM messages
+        WHERE id = $1 AND (sender_id = $2 OR recipient_id = $2)",
+        message_id,
+        client.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    if message.deleted_at.is_some() {
+        return Err(Error::Conflict("Message was deleted".into()));
+    }
+
+    let peer: Uuid = match message.sender_id == client.to_string() {
+        true => message.recipient_id.parse()?,
+        false => message.sender_id.parse()?,
+    };
+
+    if blocked_between(app_state, client, peer).await? {
+        return Err(Error::Forbidden);
+    }
+
+    Ok(peer)
+}
+
+/// Adds a reaction and returns the peer to notify, or `None` when the client
+/// had already reacted with that emoji.
+pub async fn add(
+    app_state: &Appstate,
+    client: Uuid,
+    message_id: &str,
+    emoji: &str,
+) -> Result<Option<Uuid>> {
+    validate_emoji(app_state, emoji).await?;
+    let peer = peer_of(app_state, client, message_id).await?;
+
+    let mut tx = app_state.pool().begin().await?;
+
+    // Serializes concurrent reactions of the same client on this message so
+    // the limit below cannot be raced past.
+    sqlx::query!(
+        "SELECT id FROM messages WHERE id = $1 FOR UPDATE",
+        message_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let existing = sqlx::query!(
+        "SELECT COUNT(*) AS \"count!\", COALESCE(BOOL_OR(emoji = $3), FALSE) AS \"present!\" FROM reactions
+        WHERE message_id = $1 AND client_id = $2",
+        message_id,
+        client.to_string(),
+        emoji
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if existing.present {
+        return Ok(None);
+    }
+
+    if existing.count >= MAX_REACTIONS_PER_USER {
+        return Err(Error::BadRequest(format!(
+            "At most {MAX_REACTIONS_PER_USER} reactions per message"
+        )));
+    }
+
+    sqlx::query!(
+        "INSERT INTO reactions (message_id, client_id, emoji) VALUES ($1, $2, $3)",
+        message_id,
+        client.to_string(),
+        emoji
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Some(peer))
+}
+
+/// Removes a reaction and returns the peer to notify, or `None` when there was
+/// nothing to remove.
+pub async fn remove(
+    app_state: &Appstate,
+    client: Uuid,
+    message_id: &str,
+    emoji: &str,
+) -> Result<Option<Uuid>> {
+    let peer = peer_of(app_state, client, message_id).await?;
+
+    let removed = sqlx::query!(
+        "DELETE FROM reactions WHERE message_id = $1 AND client_id = $2 AND emoji = $3",
+        message_id,
+        client.to_string(),
+        emoji
+    )
+    .execute(app_state.pool())
+    .await?
+    .rows_affected();
+
+    Ok((removed > 0).then_some(peer))
+}
+
+/// Reaction counts per message as seen by `viewer`, in the order each emoji
+/// was first used.
+pub async fn summaries(
+    app_state: &Appstate,
+    viewer: Uuid,
+    message_ids: &[String],
+) -> Result<HashMap<String, Vec<ReactionCount>>> {
+    let mut summaries: HashMap<String, Vec<ReactionCount>> = HashMap::new();
+
+    for row in sqlx::query!(
+        "SELECT message_id, emoji, COUNT(*) AS \"count!\", BOOL_OR(client_id = $2) AS \"reacted!\"
+        FROM reactions
+        WHERE message_id = ANY($1)
+        GROUP BY message_id, emoji
+        ORDER BY MIN(created_at)",
+        message_ids,
+        viewer.to_string()
+    )
+    .fetch_all(app_state.pool())
+    .await?
+    {
+        summaries
+            .entry(row.message_id)
+            .or_default()
+            .push(ReactionCount {
+                emoji: row.emoji,
+                count: row.count,
+                reacted: row.reacted,
+            });
+    }
+
+    Ok(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_emoji_are_accepted() {
+        for emoji in ["👍", "👍🏽", "👨‍👩‍👧", "🇮🇹", "1️⃣", "❤️"] {
+            assert!(is_unicode_emoji(emoji), "{emoji}");
+        }
+    }
+
+    #[test]
+    fn text_and_several_emoji_are_rejected() {
+        for emoji in [
+            "",
+            "a",
+            "ab",
+            " ",
+            "\n",
+            ":",
+            "👍👍",
+            "👍 ",
+            "🇮🇹🇮🇹",
+            "é",
+            "中",
+            "ß",
+        ] {
+            assert!(!is_unicode_emoji(emoji), "{emoji:?}");
+        }
+    }
+
+    #[test]
+    fn shortcodes() {
+        assert!(is_shortcode(":party_parrot:"));
+        assert!(is_shortcode(":a:"));
+        assert!(!is_shortcode("::"));
+        assert!(!is_shortcode(":party_parrot"));
+        assert!(!is_shortcode("party_parrot:"));
+    }
+}
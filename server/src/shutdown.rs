@@ -0,0 +1,62 @@
+//! Graceful shutdown. On SIGTERM or Ctrl-C new websocket sessions are refused
+//! and every connected one is closed with a "server restarting" close frame
+//! once the events it already sent have been handled. The HTTP server then
+//! gets the grace period to let connections finish before it exits.
+
+use std::{
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
+
+use actix::Addr;
+use actix_web::dev::ServerHandle;
+
+use crate::{
+    types::Appstate,
+    websocket::{
+        actions::Shutdown,
+        server::{Enqueue, Server},
+    },
+};
+
+pub const DEFAULT_GRACE_SECS: u64 = 30;
+/// Suggested to clients as how long to wait before reconnecting.
+pub const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+async fn terminated() {
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = actix_web::rt::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(e) => tracing::error!(error = %e, "Failed to listen for SIGTERM"),
+        }
+    }
+
+    if let Err(e) = actix_web::rt::signal::ctrl_c().await {
+        tracing::error!(error = %e, "Failed to listen for Ctrl-C");
+    }
+}
+
+pub fn spawn(app_state: Arc<Appstate>, srv: Addr<Server>, server: ServerHandle) {
+    actix_web::rt::spawn(async move {
+        terminated().await;
+        tracing::info!("Shutting down");
+
+        app_state.shutting_down.store(true, Ordering::Relaxed);
+        // Queued behind every delivery already waiting on the server, so
+        // those still reach their sessions before the close frames.
+        srv.enqueue(Shutdown {
+            retry_after: RETRY_AFTER,
+        });
+
+        server.stop(true).await;
+    });
+}
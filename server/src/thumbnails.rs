@@ -0,0 +1,192 @@
+//! Background generation of previews for image attachments. Each finished
+//! upload is processed once; the preview itself is stored next to the blobs
+//! under the same content hash, so identical files share it.
+//!
+//! Videos are marked processed without a preview: extracting a poster frame
+//! needs a video decoder such as ffmpeg, which the server does not ship.
+
+use std::sync::Arc;
+
+use actix::Addr;
+use actix_web::web;
+use image::ImageResult;
+
+use crate::{
+    attachments,
+    error::Result,
+    messages::AttachmentRef,
+    types::Appstate,
+    utils::images,
+    websocket::{
+        actions::Notify,
+        event::{ServerEvent, ServerEventType},
+        server::{Enqueue, Server},
+    },
+};
+
+const THUMBNAIL_SIZE: u32 = 400;
+const THUMBNAIL_QUALITY: u8 = 80;
+
+struct Preview {
+    width: i32,
+    height: i32,
+    blurhash: Option<String>,
+    thumbnail: Option<Vec<u8>>,
+}
+
+/// Queues preview generation for a finished upload.
+pub fn spawn(app_state: Arc<Appstate>, srv: Addr<Server>, id: String) {
+    actix_web::rt::spawn(async move {
+        if let Err(e) = process(&app_state, &srv, &id).await {
+            tracing::error!(error = %e, attachment_id = %id, "Failed to generate preview");
+        }
+    });
+}
+
+/// Queues every finished upload that was never processed, e.g. because the
+/// server stopped while its job was pending.
+pub async fn resume(app_state: Arc<Appstate>, srv: Addr<Server>) -> Result<()> {
+    let pending = sqlx::query_scalar!(
+        "SELECT id FROM attachments WHERE completed_at IS NOT NULL AND processed_at IS NULL"
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    for id in pending {
+        spawn(app_state.clone(), srv.clone(), id);
+    }
+
+    Ok(())
+}
+
+fn render(bytes: &[u8]) -> ImageResult<Preview> {
+    let image = images::decode(bytes)?;
+
+    Ok(Preview {
+        width: image.width() as i32,
+        height: image.height() as i32,
+        blurhash: images::blurhash(&image),
+        thumbnail: Some(images::encode_jpeg(
+            &image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE),
+            THUMBNAIL_QUALITY,
+        )?),
+    })
+}
+
+async fn preview(app_state: &Appstate, id: &str, sha256: &str) -> Result<Option<Preview>> {
+    // Identical content uploaded before already has a preview on disk.
+    if let Some(existing) = sqlx::query!(
+        "SELECT width AS \"width!\", height AS \"height!\", blurhash FROM attachments
+        WHERE sha256 = $1 AND has_thumbnail AND width IS NOT NULL AND height IS NOT NULL
+        LIMIT 1",
+        sha256
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    {
+        return Ok(Some(Preview {
+            width: existing.width,
+            height: existing.height,
+            blurhash: existing.blurhash,
+            thumbnail: None,
+        }));
+    }
+
+    let bytes = tokio::fs::read(attachments::blob_path(app_state, sha256)).await?;
+
+    match web::block(move || render(&bytes))
+        .await
+        .map_err(actix_web::Error::from)?
+    {
+        Ok(preview) => Ok(Some(preview)),
+        Err(e) => {
+            tracing::warn!(error = %e, attachment_id = %id, "Attachment is not a decodable image");
+            Ok(None)
+        }
+    }
+}
+
+async fn process(app_state: &Appstate, srv: &Addr<Server>, id: &str) -> Result<()> {
+    let Some(row) = sqlx::query!(
+        "SELECT content_type, sha256 FROM attachments
+        WHERE id = $1 AND completed_at IS NOT NULL AND processed_at IS NULL",
+        id
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    else {
+        return Ok(());
+    };
+
+    let preview = match row.content_type.starts_with("image/") {
+        true => preview(app_state, id, &row.sha256).await?,
+        false => None,
+    };
+
+    if let Some(thumbnail) = preview.as_ref().and_then(|p| p.thumbnail.as_ref()) {
+        let path = attachments::thumbnail_path(app_state, &row.sha256);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let tmp = path.with_extension(format!("{id}.tmp"));
+        tokio::fs::write(&tmp, thumbnail).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+    }
+
+    let updated = sqlx::query!(
+        "UPDATE attachments SET width = $2, height = $3, blurhash = $4, has_thumbnail = $5, processed_at = NOW()
+        WHERE id = $1
+        RETURNING id, uploader_id, message_id, file_name, content_type, size, width, height, blurhash, has_thumbnail",
+        id,
+        preview.as_ref().map(|p| p.width),
+        preview.as_ref().map(|p| p.height),
+        preview.as_ref().and_then(|p| p.blurhash.clone()),
+        preview.is_some()
+    )
+    .fetch_one(app_state.pool())
+    .await?;
+
+    if preview.is_none() {
+        return Ok(());
+    }
+
+    // Once the attachment was sent the recipient sees it too.
+    let mut recipients = vec![updated.uploader_id];
+    if let Some(message_id) = &updated.message_id {
+        recipients.extend(
+            sqlx::query_scalar!(
+                "SELECT recipient_id FROM messages WHERE id = $1",
+                message_id
+            )
+            .fetch_optional(app_state.pool())
+            .await?,
+        );
+    }
+
+    let event = ServerEvent::new(
+        ServerEventType::AttachmentUpdated,
+        serde_json::json!({
+            "messageId": updated.message_id,
+            "attachment": AttachmentRef {
+                id: updated.id,
+                file_name: updated.file_name,
+                content_type: updated.content_type,
+                size: updated.size,
+                width: updated.width,
+                height: updated.height,
+                blurhash: updated.blurhash,
+                has_thumbnail: updated.has_thumbnail,
+            },
+        }),
+    );
+
+    for recipient in recipients {
+        srv.enqueue(Notify {
+            to: recipient.parse()?,
+            event: event.clone(),
+        });
+    }
+
+    Ok(())
+}
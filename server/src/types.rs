@@ -1,40 +1,129 @@
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+    time::Duration,
+};
+
 use jsonwebtoken::{DecodingKey, EncodingKey, Header};
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 use ulid::Generator;
 use uuid::Uuid;
 
-use crate::error::Result;
+use crate::{access::AccessList, error::Result, utils::rate_limit::RateLimiter};
+
+const LOOKUP_LIMIT: u32 = 30;
+const DEFAULT_EDIT_WINDOW: Duration = Duration::from_secs(15 * 60);
+const LOOKUP_WINDOW: Duration = Duration::from_secs(60);
+/// Several clients may share an address, so it gets more lookups than one
+/// client does.
+const LOOKUP_LIMIT_PER_ADDRESS: u32 = 120;
+const REGISTER_LIMIT: u32 = 10;
+const REGISTER_WINDOW: Duration = Duration::from_secs(60 * 60);
 
 pub struct Appstate {
     pool: sqlx::PgPool,
+    data_dir: PathBuf,
     pub ulid: Mutex<Generator>,
     pub token_manager: TokenManager,
+    pub lookup_limiter: RateLimiter,
+    /// Registering is free, so the per-client limit alone is easy to dodge
+    /// with fresh identities.
+    pub lookup_address_limiter: RateLimiter<IpAddr>,
+    pub register_limiter: RateLimiter<IpAddr>,
+    /// How long after sending a message its author may still edit or delete it.
+    pub edit_window: Duration,
+    /// Set once the server started shutting down. New websocket sessions are
+    /// refused from then on.
+    pub shutting_down: AtomicBool,
+    pub access: AccessList,
+    /// Grants access to the admin API without an admin token.
+    pub admin_key: Option<String>,
 }
 
 impl Appstate {
-    pub fn new(pool: sqlx::PgPool, token_manager: TokenManager) -> Self {
+    pub fn new(
+        pool: sqlx::PgPool,
+        token_manager: TokenManager,
+        data_dir: PathBuf,
+        edit_window: Option<Duration>,
+        access: AccessList,
+        admin_key: Option<String>,
+    ) -> Self {
         Self {
             pool,
+            data_dir,
             token_manager,
             ulid: Mutex::new(Generator::new()),
+            lookup_limiter: RateLimiter::new(LOOKUP_LIMIT, LOOKUP_WINDOW),
+            lookup_address_limiter: RateLimiter::new(LOOKUP_LIMIT_PER_ADDRESS, LOOKUP_WINDOW),
+            register_limiter: RateLimiter::new(REGISTER_LIMIT, REGISTER_WINDOW),
+            edit_window: edit_window.unwrap_or(DEFAULT_EDIT_WINDOW),
+            shutting_down: AtomicBool::new(false),
+            access,
+            admin_key,
         }
     }
 
     pub fn pool(&self) -> &sqlx::PgPool {
         &self.pool
     }
+
+    /// Root directory for everything the server stores on disk.
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+}
+
+pub const ADMIN_ROLE: &str = "admin";
+
+/// What a token may be used for.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Conversations, messages, attachments and the websocket.
+    Messages,
+    /// Contacts, blocks and looking up other users.
+    Contacts,
+    /// The client's own profile, avatar and settings.
+    Profile,
+    Admin,
+}
+
+impl Scope {
+    /// The scopes granted to a client with the given roles.
+    pub fn for_roles(roles: &[String]) -> Vec<Scope> {
+        let mut scopes = vec![Scope::Messages, Scope::Contacts, Scope::Profile];
+
+        if roles.iter().any(|role| role == ADMIN_ROLE) {
+            scopes.push(Scope::Admin);
+        }
+
+        scopes
+    }
+}
+
+fn default_scopes() -> Vec<Scope> {
+    Scope::for_roles(&[])
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Claims {
     pub id: Uuid,
     pub exp: usize,
+    /// Tokens issued before this field existed count as issued at the epoch.
+    #[serde(default)]
+    pub iat: usize,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Tokens issued before scopes existed get those of a regular client.
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<Scope>,
 }
 
 #[derive(Clone)]
 pub struct TokenManager {
-    secret: String,
     enc_key: EncodingKey,
     dec_key: DecodingKey,
 }
@@ -43,11 +132,7 @@ impl TokenManager {
     pub fn new(secret: String) -> Self {
         let enc_key = EncodingKey::from_secret(secret.as_bytes());
         let dec_key = DecodingKey::from_secret(secret.as_bytes());
-        Self {
-            secret,
-            enc_key,
-            dec_key,
-        }
+        Self { enc_key, dec_key }
     }
 
     pub fn generate_token(&self, payload: &Claims) -> Result<String> {
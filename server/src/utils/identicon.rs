@@ -0,0 +1,61 @@
+use image::{Rgba, RgbaImage};
+use uuid::Uuid;
+
+const GRID: u32 = 5;
+const BACKGROUND: Rgba<u8> = Rgba([240, 240, 240, 255]);
+
+/// Renders a mirrored 5x5 identicon for `id`. The same id always yields the
+/// same picture.
+pub fn render(id: &Uuid, size: u32) -> RgbaImage {
+    let bytes = id.as_bytes();
+    let hue = u16::from_be_bytes([bytes[0], bytes[1]]) % 360;
+    let color = hsl_to_rgba(hue as f32, 0.55, 0.55);
+
+    // Only the left three columns are random, the right two mirror them.
+    let mut cells = [[false; GRID as usize]; GRID as usize];
+    for (row, cells) in cells.iter_mut().enumerate() {
+        for col in 0..3 {
+            let bit = row * 3 + col;
+            let on = (bytes[2 + bit / 8] >> (bit % 8)) & 1 == 1;
+            cells[col] = on;
+            cells[GRID as usize - 1 - col] = on;
+        }
+    }
+
+    // Half a cell of margin on every side.
+    let cell = size as f32 / (GRID + 1) as f32;
+    let margin = cell / 2.0;
+
+    RgbaImage::from_fn(size, size, |x, y| {
+        let col = (x as f32 - margin) / cell;
+        let row = (y as f32 - margin) / cell;
+
+        if col < 0.0 || row < 0.0 || col >= GRID as f32 || row >= GRID as f32 {
+            return BACKGROUND;
+        }
+
+        if cells[row as usize][col as usize] {
+            color
+        } else {
+            BACKGROUND
+        }
+    })
+}
+
+fn hsl_to_rgba(h: f32, s: f32, l: f32) -> Rgba<u8> {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let m = l - c / 2.0;
+
+    let (r, g, b) = match h as u32 {
+        0..60 => (c, x, 0.0),
+        60..120 => (x, c, 0.0),
+        120..180 => (0.0, c, x),
+        180..240 => (0.0, x, c),
+        240..300 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    let channel = |v: f32| ((v + m) * 255.0).round() as u8;
+    Rgba([channel(r), channel(g), channel(b), 255])
+}
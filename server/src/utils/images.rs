@@ -0,0 +1,63 @@
+use std::io::Cursor;
+
+use image::{
+    DynamicImage, ImageFormat, ImageReader, ImageResult, Limits, Rgb, RgbImage,
+    codecs::jpeg::JpegEncoder, imageops::FilterType,
+};
+
+const MAX_DIMENSION: u32 = 8192;
+const MAX_ALLOC: u64 = 256 * 1024 * 1024;
+
+/// Decodes an image of any supported format, refusing anything that would
+/// decode to more than `MAX_DIMENSION` pixels on a side.
+pub fn decode(bytes: &[u8]) -> ImageResult<DynamicImage> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_ALLOC);
+
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    reader.limits(limits);
+    reader.decode()
+}
+
+/// Crops the largest centered square out of `image` and scales it to `size`.
+pub fn square(image: &DynamicImage, size: u32) -> DynamicImage {
+    let side = image.width().min(image.height());
+    let x = (image.width() - side) / 2;
+    let y = (image.height() - side) / 2;
+
+    image
+        .crop_imm(x, y, side, side)
+        .resize_exact(size, size, FilterType::Lanczos3)
+}
+
+pub fn encode_png(image: &DynamicImage) -> ImageResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+
+    Ok(bytes)
+}
+
+/// Encodes a JPEG preview, flattening any transparency onto white.
+pub fn encode_jpeg(image: &DynamicImage, quality: u8) -> ImageResult<Vec<u8>> {
+    let rgba = image.to_rgba8();
+    let flattened = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u16 * a as u16 + 255 * (255 - a as u16)) / 255) as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    });
+
+    let mut bytes = Vec::new();
+    flattened.write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, quality))?;
+
+    Ok(bytes)
+}
+
+/// A compact placeholder for `image`, computed from a downscaled copy since
+/// the hash only carries a handful of components anyway.
+pub fn blurhash(image: &DynamicImage) -> Option<String> {
+    let small = image.thumbnail(32, 32).to_rgba8();
+
+    blurhash::encode(4, 3, small.width(), small.height(), small.as_raw()).ok()
+}
@@ -0,0 +1,30 @@
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_FILTER: &str = "info,sqlx=warn";
+
+/// Installs the global subscriber.
+///
+/// `filter` uses the `EnvFilter` syntax, e.g. `info,server::websocket=debug`,
+/// and `format` switches to one JSON object per line when it is `json`.
+pub fn init(filter: Option<&str>, format: Option<&str>) {
+    let (filter, invalid) = match filter.map(EnvFilter::try_new) {
+        Some(Ok(filter)) => (filter, None),
+        Some(Err(e)) => (EnvFilter::new(DEFAULT_FILTER), Some(e)),
+        None => (EnvFilter::new(DEFAULT_FILTER), None),
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match format {
+        Some("json") => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+        _ => builder.init(),
+    }
+
+    if let Some(e) = invalid {
+        tracing::warn!(error = %e, "Invalid LOG_FILTER, falling back to {}", DEFAULT_FILTER);
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::HttpRequest;
+use uuid::Uuid;
+
+use crate::error::{Error, Result};
+
+/// Fixed-window limiter, keyed by client id unless stated otherwise.
+pub struct RateLimiter<K = Uuid> {
+    limit: u32,
+    window: Duration,
+    hits: Mutex<HashMap<K, (Instant, u32)>>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a hit for `key`, failing once it has used up its window.
+    pub fn check(&self, key: K) -> Result<()> {
+        let now = Instant::now();
+        let mut hits = self.hits.lock().unwrap_or_else(|e| e.into_inner());
+
+        if hits.len() > 10_000 {
+            hits.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+
+        let (start, count) = hits.entry(key).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+
+        if *count >= self.limit {
+            return Err(Error::TooManyRequests);
+        }
+
+        *count += 1;
+        Ok(())
+    }
+}
+
+impl RateLimiter<IpAddr> {
+    /// Records a hit for the address the request came from. That is the
+    /// socket's peer, forwarding headers are set by the client and would let
+    /// it pick its own key.
+    pub fn check_peer(&self, req: &HttpRequest) -> Result<()> {
+        match req.peer_addr() {
+            Some(addr) => self.check(addr.ip()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_limited_separately() {
+        let limiter = RateLimiter::<IpAddr>::new(2, Duration::from_secs(60));
+        let a = IpAddr::from([10, 0, 0, 1]);
+        let b = IpAddr::from([10, 0, 0, 2]);
+
+        assert!(limiter.check(a).is_ok());
+        assert!(limiter.check(a).is_ok());
+        assert!(matches!(limiter.check(a), Err(Error::TooManyRequests)));
+        assert!(limiter.check(b).is_ok());
+    }
+
+    #[test]
+    fn windows_reset() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(10));
+        let id = Uuid::new_v4();
+
+        assert!(limiter.check(id).is_ok());
+        assert!(limiter.check(id).is_err());
+        std::thread::sleep(Duration::from_millis(15));
+        assert!(limiter.check(id).is_ok());
+    }
+}
@@ -1,9 +1,168 @@
 use actix::Message;
+use actix_web_actors::ws::CloseCode;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
 use uuid::Uuid;
 
+use crate::{messages, websocket::event::ServerEvent};
+
 #[derive(Message)]
 #[rtype(result = "String")]
 pub struct ChangeId {
     pub uid: Uuid,
     pub new_id: Uuid,
 }
+
+/// Push an event to a client if it currently has a live session.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Notify {
+    pub to: Uuid,
+    pub event: ServerEvent,
+}
+
+/// Two clients became contacts of each other.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ContactAdded {
+    pub a: Uuid,
+    pub b: Uuid,
+}
+
+/// Two clients are no longer contacts.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ContactRemoved {
+    pub a: Uuid,
+    pub b: Uuid,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetContactsOnly {
+    pub id: Uuid,
+    pub contacts_only: bool,
+}
+
+/// `id` blocked or unblocked `other`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct BlockChanged {
+    pub id: Uuid,
+    pub other: Uuid,
+    pub blocked: bool,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MuteChanged {
+    pub id: Uuid,
+    pub peer: Uuid,
+    pub muted: bool,
+}
+
+/// Fan a client's new profile out to its contacts.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ProfileUpdated {
+    pub id: Uuid,
+    pub profile: serde_json::Value,
+}
+
+/// Push a stored chat message to its recipient if it is online.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Deliver {
+    pub message: messages::Message,
+    /// Author of the thread root when the message is a reply. Replies reach
+    /// the root author unmuted.
+    pub root_author: Option<Uuid>,
+    /// When the message reached the server, for the routing latency metric.
+    pub received_at: Instant,
+}
+
+/// Push an event about a message to both participants of its conversation.
+/// The peer is skipped while either side has blocked the other.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ToConversation {
+    pub author: Uuid,
+    pub peer: Uuid,
+    pub event: ServerEvent,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Typing {
+    pub from: Uuid,
+    pub to: Uuid,
+    pub typing: bool,
+}
+
+/// Which of the given clients currently have a live session.
+#[derive(Message)]
+#[rtype(result = "HashSet<Uuid>")]
+pub struct GetOnline {
+    pub ids: Vec<Uuid>,
+}
+
+/// Round-trips through the mailbox to show the actor is still handling
+/// messages.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Probe;
+
+/// Ask every session to close so clients reconnect to the next instance.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Shutdown {
+    /// How long clients should wait before reconnecting.
+    pub retry_after: Duration,
+}
+
+/// Sent to a session on shutdown. It stops taking new events and reports
+/// back with [`Drained`] once the ones it is still working on are done.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Drain;
+
+/// A draining session has nothing left in flight.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Drained {
+    pub id: Uuid,
+}
+
+/// Close a session, e.g. with a "server restarting" close frame.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Close {
+    pub code: CloseCode,
+    pub description: String,
+}
+
+/// A live session as shown to admins.
+#[derive(Serialize)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub connection_id: Uuid,
+    pub connected_at: DateTime<Utc>,
+    pub remote_addr: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<SessionInfo>")]
+pub struct ListSessions;
+
+/// Close a client's session. Replies whether it had one.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct Kick {
+    pub id: Uuid,
+    pub code: CloseCode,
+    pub reason: String,
+}
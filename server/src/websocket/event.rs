@@ -1,15 +1,58 @@
 use crate::websocket::WsClient;
-use actix::{Addr, Message, Recipient};
-use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use actix::{Addr, Message};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use uuid::Uuid;
 
 #[derive(Deserialize, Serialize, Debug)]
-#[serde(rename_all = "UPPERCASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ClientEventType {
+    /// Authenticates a socket opened without a token. Must be the first
+    /// event.
+    Auth,
+    /// Replaces the session's token with a fresh one before it expires.
+    Reauth,
     ChangeMyId,
+    SendMessage,
+    EditMessage,
+    DeleteMessage,
+    AddReaction,
+    RemoveReaction,
+    Typing,
 }
 
-#[derive(Message, Serialize, Deserialize, Debug)]
+/// Events pushed from the server to a connected client.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ServerEventType {
+    Error,
+    Authenticated,
+    /// The session's token expires soon. The client has to send REAUTH
+    /// before then or the socket is closed.
+    AuthExpiring,
+    /// Round-trip time of the socket, after each heartbeat.
+    ConnectionQuality,
+    ContactRequest,
+    ContactRequestAccepted,
+    ContactRequestDeclined,
+    ContactRequestCancelled,
+    ContactRemoved,
+    ProfileUpdated,
+    Presence,
+    MessageSent,
+    MessageReceived,
+    MessageEdited,
+    MessageDeleted,
+    ReactionAdded,
+    ReactionRemoved,
+    ThreadUpdated,
+    MessagesExpired,
+    DisappearingChanged,
+    AttachmentUpdated,
+    Typing,
+}
+
+#[derive(Message, Serialize, Deserialize, Debug, Clone)]
 #[rtype(result = "()")]
 #[serde(rename_all = "camelCase")]
 pub struct Event<T = serde_json::Value, E = ClientEventType> {
@@ -17,15 +60,111 @@ pub struct Event<T = serde_json::Value, E = ClientEventType> {
     pub data: T,
 }
 
+pub type ServerEvent = Event<serde_json::Value, ServerEventType>;
+
+impl ServerEvent {
+    pub fn new(event_type: ServerEventType, data: impl Serialize) -> Self {
+        Self {
+            event_type,
+            data: serde_json::to_value(data).unwrap_or_default(),
+        }
+    }
+
+    /// An ERROR event. `code` is one of the codes of [`crate::error::Error`]
+    /// and `context` an object of fields identifying what failed, e.g. the
+    /// nonce of a message.
+    pub fn error(
+        code: &'static str,
+        message: impl Into<String>,
+        mut context: serde_json::Value,
+    ) -> Self {
+        if let Some(fields) = context.as_object_mut() {
+            fields.insert("code".into(), code.into());
+            fields.insert("message".into(), message.into().into());
+        }
+
+        Self {
+            event_type: ServerEventType::Error,
+            data: context,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthPayload {
+    pub token: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SendMessagePayload {
+    pub to: Uuid,
+    #[serde(default)]
+    pub content: String,
+    /// Ids of finished uploads to send along with the message.
+    #[serde(default)]
+    pub attachments: Vec<String>,
+    /// Id of the message this one replies to.
+    pub reply_to: Option<String>,
+    /// Echoed back in MESSAGE_SENT so the sender can match the stored message
+    /// to the one it optimistically displayed.
+    pub nonce: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EditMessagePayload {
+    pub id: String,
+    pub content: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteMessagePayload {
+    pub id: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionPayload {
+    pub message_id: String,
+    pub emoji: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TypingPayload {
+    pub to: Uuid,
+    pub typing: bool,
+}
+
+/// What the server needs to know about a client to decide who may reach it.
+#[derive(Default, Clone)]
+pub struct Roster {
+    pub contacts: HashSet<Uuid>,
+    /// When false the client accepts messages from anyone, not just contacts.
+    pub contacts_only: bool,
+    /// Nothing is exchanged with these clients, in either direction.
+    pub blocked: HashSet<Uuid>,
+    /// Peers whose messages are still delivered but flagged as muted.
+    pub muted: HashSet<Uuid>,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Connect {
     pub id: Uuid,
     pub addr: Addr<WsClient>,
+    pub roster: Roster,
+    pub connection_id: Uuid,
+    pub remote_addr: Option<String>,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Disconnect {
     pub id: Uuid,
+    /// Tells the closed connection apart from a newer one of the same client.
+    pub connection_id: Uuid,
 }
@@ -0,0 +1,154 @@
+//! Liveness and round-trip time of a websocket session. Pings carry the time
+//! they were sent, so every pong is a round-trip sample.
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// A client that sent nothing for this long, not even a pong, is gone.
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Gain of the smoothed RTT, as in RFC 6298.
+const RTT_GAIN: f64 = 1.0 / 8.0;
+/// Gain of the jitter estimate, as in RFC 3550.
+const JITTER_GAIN: f64 = 1.0 / 16.0;
+
+/// Connection quality as reported to the client, in milliseconds.
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct Quality {
+    /// The latest sample.
+    pub rtt_ms: f64,
+    pub smoothed_rtt_ms: f64,
+    pub jitter_ms: f64,
+}
+
+impl Quality {
+    /// Folds a new round-trip sample into the previous estimates.
+    fn sample(previous: Option<Quality>, rtt_ms: f64) -> Quality {
+        match previous {
+            None => Quality {
+                rtt_ms,
+                smoothed_rtt_ms: rtt_ms,
+                jitter_ms: 0.0,
+            },
+            Some(previous) => Quality {
+                rtt_ms,
+                smoothed_rtt_ms: previous.smoothed_rtt_ms
+                    + RTT_GAIN * (rtt_ms - previous.smoothed_rtt_ms),
+                jitter_ms: previous.jitter_ms
+                    + JITTER_GAIN * ((rtt_ms - previous.rtt_ms).abs() - previous.jitter_ms),
+            },
+        }
+    }
+}
+
+pub struct Heartbeat {
+    /// Ping payloads are the microseconds elapsed since this instant.
+    epoch: Instant,
+    last_seen: Instant,
+    quality: Option<Quality>,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        let now = Instant::now();
+
+        Self {
+            epoch: now,
+            last_seen: now,
+            quality: None,
+        }
+    }
+}
+
+impl Heartbeat {
+    /// Records that the client sent something.
+    pub fn seen(&mut self) {
+        self.last_seen = Instant::now();
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.last_seen.elapsed() > CLIENT_TIMEOUT
+    }
+
+    pub fn ping_payload(&self) -> [u8; 8] {
+        (self.epoch.elapsed().as_micros() as u64).to_be_bytes()
+    }
+
+    /// Takes a round-trip sample from a pong echoing one of our pings.
+    /// Returns `None` for pongs that did not.
+    pub fn pong(&mut self, payload: &[u8]) -> Option<Quality> {
+        self.seen();
+
+        let sent = Duration::from_micros(u64::from_be_bytes(payload.try_into().ok()?));
+        let rtt_ms = self.epoch.elapsed().checked_sub(sent)?.as_secs_f64() * 1000.0;
+
+        let quality = Quality::sample(self.quality, rtt_ms);
+        self.quality = Some(quality);
+        Some(quality)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_sample_seeds_the_estimates() {
+        let quality = Quality::sample(None, 40.0);
+
+        assert_eq!(quality.rtt_ms, 40.0);
+        assert_eq!(quality.smoothed_rtt_ms, 40.0);
+        assert_eq!(quality.jitter_ms, 0.0);
+    }
+
+    #[test]
+    fn later_samples_are_smoothed() {
+        let first = Quality::sample(None, 20.0);
+        let second = Quality::sample(Some(first), 100.0);
+
+        assert_eq!(second.rtt_ms, 100.0);
+        assert_eq!(second.smoothed_rtt_ms, 20.0 + 80.0 / 8.0);
+        assert_eq!(second.jitter_ms, 80.0 / 16.0);
+
+        let third = Quality::sample(Some(second), 100.0);
+        assert_eq!(third.smoothed_rtt_ms, 30.0 + 70.0 / 8.0);
+        assert_eq!(third.jitter_ms, 5.0 - 5.0 / 16.0);
+    }
+
+    #[test]
+    fn steady_samples_converge() {
+        let mut quality = Quality::sample(None, 200.0);
+
+        for _ in 0..200 {
+            quality = Quality::sample(Some(quality), 50.0);
+        }
+
+        assert!((quality.smoothed_rtt_ms - 50.0).abs() < 0.01);
+        assert!(quality.jitter_ms < 0.01);
+    }
+
+    #[test]
+    fn pong_measures_its_ping() {
+        let mut heartbeat = Heartbeat::default();
+        let payload = heartbeat.ping_payload();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let quality = heartbeat.pong(&payload).expect("a sample");
+        assert!(quality.rtt_ms >= 20.0);
+        assert_eq!(quality.smoothed_rtt_ms, quality.rtt_ms);
+    }
+
+    #[test]
+    fn foreign_pongs_are_ignored() {
+        let mut heartbeat = Heartbeat::default();
+
+        assert!(heartbeat.pong(b"").is_none());
+        assert!(heartbeat.pong(b"hello").is_none());
+        // Sent in the future, as far as this heartbeat can tell.
+        assert!(heartbeat.pong(&u64::MAX.to_be_bytes()).is_none());
+        assert!(!heartbeat.timed_out());
+    }
+}
@@ -1,53 +1,550 @@
 pub mod actions;
 pub mod event;
+pub mod heartbeat;
 pub mod server;
 
-use crate::types::Appstate;
-use crate::websocket::event::{ClientEventType, Connect, Disconnect, Event};
-use crate::websocket::server::Server;
-use actix::{Actor, Addr, AsyncContext, Context, Message, Recipient};
+use crate::auth::{Authenticated, scopes};
+use crate::error::Error;
+use crate::messages;
+use crate::metrics::METRICS;
+use crate::reactions;
+use crate::types::{Appstate, Claims};
+use crate::websocket::event::{
+    AuthPayload, ClientEventType, Connect, DeleteMessagePayload, Disconnect, EditMessagePayload,
+    Event, ReactionPayload, Roster, SendMessagePayload, ServerEvent, ServerEventType,
+    TypingPayload,
+};
+use crate::websocket::heartbeat::{HEARTBEAT_INTERVAL, Heartbeat};
+use crate::websocket::server::{Enqueue, Server};
+use actix::{Actor, ActorFutureExt, Addr, AsyncContext, Message, SpawnHandle, WrapFuture};
 use actix::{ActorContext, StreamHandler};
-use actix_web_actors::ws::{self};
-use sqlx::PgPool;
+use actix_web_actors::ws::{self, CloseCode};
+use chrono::Utc;
+use serde_json::json;
+use std::collections::HashSet;
 use std::sync::Arc;
-use std::{
-    collections::HashMap,
-    time::{Duration, Instant},
-};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
 use uuid::Uuid;
 
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a socket opened without a token has to send its AUTH event.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long before the token expires the client is asked to send REAUTH.
+const EXPIRY_NOTICE: Duration = Duration::from_secs(60);
 
-#[derive(Message)]
-#[rtype(result = "String")]
-pub struct GetUID;
+/// The socket was not authenticated in time, or with a valid token.
+pub const CLOSE_UNAUTHENTICATED: CloseCode = CloseCode::Other(4001);
+/// The session's token expired without the client sending REAUTH.
+pub const CLOSE_TOKEN_EXPIRED: CloseCode = CloseCode::Other(4002);
+/// The token is valid but its client may not connect, e.g. it is banned.
+pub const CLOSE_FORBIDDEN: CloseCode = CloseCode::Other(4003);
+
+/// Loads what a session of `id` needs, failing if the client does not exist.
+pub async fn load_session(app_state: &Appstate, id: Uuid) -> crate::error::Result<Roster> {
+    let Some(client) = sqlx::query!(
+        "SELECT contacts_only FROM clients WHERE id = $1",
+        id.to_string()
+    )
+    .fetch_optional(app_state.pool())
+    .await?
+    else {
+        tracing::warn!(client_id = %id, "Token belongs to an unknown client");
+        METRICS.auth_failure(&Error::Unauthorized);
+        return Err(Error::Unauthorized);
+    };
+
+    let ids = |rows: Vec<String>| -> HashSet<Uuid> {
+        rows.iter()
+            .filter_map(|id| Uuid::parse_str(id).ok())
+            .collect()
+    };
+
+    let contacts = sqlx::query_scalar!(
+        "SELECT contact_id FROM contacts WHERE client_id = $1",
+        id.to_string()
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    let blocked = sqlx::query_scalar!(
+        "SELECT blocked_id FROM blocks WHERE client_id = $1",
+        id.to_string()
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    let muted = sqlx::query_scalar!(
+        "SELECT peer_id FROM mutes WHERE client_id = $1",
+        id.to_string()
+    )
+    .fetch_all(app_state.pool())
+    .await?;
+
+    Ok(Roster {
+        contacts: ids(contacts),
+        contacts_only: client.contacts_only,
+        blocked: ids(blocked),
+        muted: ids(muted),
+    })
+}
+
+fn close(ctx: &mut ws::WebsocketContext<WsClient>, code: CloseCode, description: String) {
+    tracing::info!(reason = %description, "Closing session");
+
+    ctx.close(Some(ws::CloseReason {
+        code,
+        description: Some(description),
+    }));
+    ctx.stop();
+}
+
+#[derive(PartialEq, Eq)]
+enum AuthState {
+    /// Waiting for the AUTH event.
+    Pending,
+    /// The AUTH event's token is being checked.
+    Authenticating,
+    Authenticated,
+}
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct WsClient {
+    /// Nil until the socket is authenticated.
     id: Uuid,
-    last_seen: Instant,
+    auth: AuthState,
+    /// When the session's token expires, in seconds since the epoch.
+    expires_at: usize,
+    /// Sends AUTH_EXPIRING, then closes the socket once the token expired.
+    expiry_timer: Option<SpawnHandle>,
+    heartbeat: Heartbeat,
     server_addr: Addr<Server>,
     app_state: Arc<Appstate>,
+    roster: Roster,
+    connection_id: Uuid,
+    remote_addr: Option<String>,
+    /// Carries the client and connection ids on everything logged for this
+    /// session.
+    span: tracing::Span,
+    /// Events from the client that are still being processed.
+    in_flight: usize,
+    /// Set on shutdown. New events are ignored from then on.
+    draining: bool,
 }
 
 impl WsClient {
-    pub fn new(id: Uuid, server_addr: Addr<Server>, app_state: Arc<Appstate>) -> Self {
+    /// A socket that has to authenticate with an AUTH event before anything
+    /// else, see [`WsClient::authenticated`].
+    pub fn new(
+        server_addr: Addr<Server>,
+        app_state: Arc<Appstate>,
+        connection_id: Uuid,
+        remote_addr: Option<String>,
+    ) -> Self {
         WsClient {
-            id,
+            id: Uuid::nil(),
+            auth: AuthState::Pending,
+            expires_at: 0,
+            expiry_timer: None,
             server_addr,
-            last_seen: Instant::now(),
+            heartbeat: Heartbeat::default(),
             app_state,
+            roster: Roster::default(),
+            connection_id,
+            remote_addr,
+            span: tracing::info_span!(
+                parent: None,
+                "ws",
+                client_id = tracing::field::Empty,
+                %connection_id
+            ),
+            in_flight: 0,
+            draining: false,
+        }
+    }
+
+    /// Marks a socket whose handshake already carried a token as
+    /// authenticated.
+    pub fn authenticated(mut self, claims: &Claims, roster: Roster) -> Self {
+        self.set_identity(claims, roster);
+        self
+    }
+
+    fn set_identity(&mut self, claims: &Claims, roster: Roster) {
+        let id = claims.id;
+        self.id = id;
+        self.expires_at = claims.exp;
+        self.roster = roster;
+        self.auth = AuthState::Authenticated;
+        self.span.record("client_id", tracing::field::display(id));
+    }
+
+    fn connect(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        self.server_addr.enqueue(Connect {
+            id: self.id,
+            addr: ctx.address(),
+            roster: std::mem::take(&mut self.roster),
+            connection_id: self.connection_id,
+            remote_addr: self.remote_addr.take(),
+        });
+        self.schedule_expiry(ctx);
+    }
+
+    fn time_left(&self) -> Duration {
+        let secs = (self.expires_at as i64).saturating_sub(Utc::now().timestamp());
+        Duration::from_secs(secs.max(0) as u64)
+    }
+
+    /// (Re)arms the timer that warns the client about its token expiring and
+    /// closes the socket once it did.
+    fn schedule_expiry(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Some(timer) = self.expiry_timer.take() {
+            ctx.cancel_future(timer);
+        }
+
+        let notice_in = self.time_left().saturating_sub(EXPIRY_NOTICE);
+
+        self.expiry_timer = Some(ctx.run_later(notice_in, |client, ctx| {
+            let _span = client.span.clone().entered();
+            ctx.notify(ServerEvent::new(
+                ServerEventType::AuthExpiring,
+                json!({"expiresAt": client.expires_at}),
+            ));
+
+            client.expiry_timer = Some(ctx.run_later(client.time_left(), |client, ctx| {
+                let _span = client.span.clone().entered();
+                close(ctx, CLOSE_TOKEN_EXPIRED, "token expired".into());
+            }));
+        }));
+    }
+
+    fn notify_authenticated(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.notify(ServerEvent::new(
+            ServerEventType::Authenticated,
+            json!({"id": self.id, "expiresAt": self.expires_at}),
+        ));
+    }
+
+    /// Checks the token of an AUTH event and joins the server once it holds.
+    fn authenticate(&mut self, payload: AuthPayload, ctx: &mut ws::WebsocketContext<Self>) {
+        self.auth = AuthState::Authenticating;
+        let app_state = self.app_state.clone();
+
+        let session = async move {
+            let auth = Authenticated::<scopes::Messages>::from_token(&app_state, &payload.token)?;
+            let roster = load_session(&app_state, auth.id()).await?;
+
+            Ok::<_, Error>((auth.claims, roster))
+        };
+
+        ctx.spawn(session.instrument(self.span.clone()).into_actor(self).map(
+            |session, client, ctx| {
+                let _span = client.span.clone().entered();
+
+                let (claims, roster) = match session {
+                    Ok(session) => session,
+                    Err(Error::Forbidden) => {
+                        return close(ctx, CLOSE_FORBIDDEN, "forbidden".into());
+                    }
+                    Err(e @ (Error::Unauthorized | Error::Jwt(_))) => {
+                        tracing::debug!(error = %e, "Rejected AUTH token");
+                        return close(ctx, CLOSE_UNAUTHENTICATED, "invalid token".into());
+                    }
+                    Err(e) => {
+                        tracing::error!(error = %e, "Failed to authenticate session");
+                        return close(ctx, CloseCode::Error, "authentication failed".into());
+                    }
+                };
+
+                if client.app_state.shutting_down.load(Ordering::Relaxed) {
+                    return close(ctx, CloseCode::Restart, "server restarting".into());
+                }
+
+                client.set_identity(&claims, roster);
+                tracing::info!("Client authenticated");
+                client.connect(ctx);
+                client.notify_authenticated(ctx);
+            },
+        ));
+    }
+
+    /// Swaps the session's token for the fresher one of a REAUTH event. An
+    /// invalid token leaves the old expiry in place.
+    fn reauthenticate(&mut self, payload: AuthPayload, ctx: &mut ws::WebsocketContext<Self>) {
+        let auth =
+            match Authenticated::<scopes::Messages>::from_token(&self.app_state, &payload.token) {
+                Ok(auth) if auth.id() == self.id => auth,
+                Ok(_) | Err(Error::Forbidden) => {
+                    return close(ctx, CLOSE_FORBIDDEN, "forbidden".into());
+                }
+                Err(e) => {
+                    tracing::debug!(error = %e, "Rejected REAUTH token");
+                    return ctx.notify(ServerEvent::error(e.code(), e.message(), json!({})));
+                }
+            };
+
+        self.expires_at = auth.claims.exp;
+        self.schedule_expiry(ctx);
+        tracing::info!("Client reauthenticated");
+        self.notify_authenticated(ctx);
+    }
+
+    /// Processes an event in the background, keeping track of it so a
+    /// shutdown can wait for it.
+    fn spawn_task(
+        &mut self,
+        task: impl Future<Output = ()> + 'static,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        self.in_flight += 1;
+
+        ctx.spawn(
+            task.instrument(self.span.clone())
+                .into_actor(self)
+                .map(|_, client, _| {
+                    client.in_flight -= 1;
+                    client.report_drained();
+                }),
+        );
+    }
+
+    fn report_drained(&self) {
+        if self.draining && self.in_flight == 0 {
+            self.server_addr.enqueue(actions::Drained { id: self.id });
+        }
+    }
+
+    fn send_message(&mut self, payload: SendMessagePayload, ctx: &mut ws::WebsocketContext<Self>) {
+        let received_at = Instant::now();
+        let from = self.id;
+        let app_state = self.app_state.clone();
+        let server_addr = self.server_addr.clone();
+        let addr = ctx.address();
+
+        self.spawn_task(
+            async move {
+                let to = payload.to;
+                let nonce = payload.nonce;
+
+                let stored = async {
+                    let id = app_state.ulid.lock().await.generate()?;
+                    messages::authorize(&app_state, from, to).await?;
+                    messages::store(
+                        &app_state,
+                        id,
+                        from,
+                        to,
+                        payload.content,
+                        &payload.attachments,
+                        payload.reply_to.as_deref(),
+                    )
+                    .await
+                }
+                .await;
+
+                match stored {
+                    Ok((message, thread)) => {
+                        addr.do_send(ServerEvent::new(
+                            ServerEventType::MessageSent,
+                            json!({"nonce": nonce, "message": message}),
+                        ));
+                        server_addr.enqueue(actions::Deliver {
+                            message,
+                            root_author: thread.as_ref().map(|t| t.root_author),
+                            received_at,
+                        });
+
+                        if let Some(thread) = thread {
+                            server_addr.enqueue(actions::ToConversation {
+                                author: from,
+                                peer: to,
+                                event: ServerEvent::new(ServerEventType::ThreadUpdated, thread),
+                            });
+                        }
+                    }
+                    Err(e) => {
+                        let code = e.code();
+                        let message = match e {
+                            Error::BadRequest(reason) => reason,
+                            Error::Forbidden => "Message could not be delivered".into(),
+                            e => {
+                                tracing::error!(error = %e, %to, "Failed to store message");
+                                "Message could not be sent".into()
+                            }
+                        };
+
+                        addr.do_send(ServerEvent::error(
+                            code,
+                            message,
+                            json!({"to": to, "nonce": nonce}),
+                        ));
+                    }
+                }
+            },
+            ctx,
+        );
+    }
+
+    fn edit_message(&mut self, payload: EditMessagePayload, ctx: &mut ws::WebsocketContext<Self>) {
+        let author = self.id;
+        let app_state = self.app_state.clone();
+
+        self.change_message(
+            payload.id.clone(),
+            ServerEventType::MessageEdited,
+            ctx,
+            async move { messages::edit(&app_state, author, &payload.id, payload.content).await },
+        );
+    }
+
+    fn delete_message(
+        &mut self,
+        payload: DeleteMessagePayload,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let author = self.id;
+        let app_state = self.app_state.clone();
+
+        self.change_message(
+            payload.id.clone(),
+            ServerEventType::MessageDeleted,
+            ctx,
+            async move { messages::delete(&app_state, author, &payload.id).await },
+        );
+    }
+
+    fn react(
+        &mut self,
+        payload: ReactionPayload,
+        added: bool,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let from = self.id;
+        let app_state = self.app_state.clone();
+        let server_addr = self.server_addr.clone();
+        let addr = ctx.address();
+
+        self.spawn_task(
+            async move {
+                let ReactionPayload { message_id, emoji } = payload;
+
+                let changed = match added {
+                    true => reactions::add(&app_state, from, &message_id, &emoji).await,
+                    false => reactions::remove(&app_state, from, &message_id, &emoji).await,
+                };
+
+                let event_type = match added {
+                    true => ServerEventType::ReactionAdded,
+                    false => ServerEventType::ReactionRemoved,
+                };
+
+                match changed {
+                    Ok(Some(peer)) => server_addr.enqueue(actions::ToConversation {
+                        author: from,
+                        peer,
+                        event: ServerEvent::new(
+                            event_type,
+                            json!({"messageId": message_id, "from": from, "emoji": emoji}),
+                        ),
+                    }),
+                    Ok(None) => {}
+                    Err(e) => {
+                        let code = e.code();
+                        let message = match e {
+                            Error::BadRequest(reason) | Error::Conflict(reason) => reason,
+                            Error::NotFound => "Message not found".into(),
+                            Error::Forbidden => "You cannot react to this message".into(),
+                            e => {
+                                tracing::error!(error = %e, %message_id, "Failed to update reaction");
+                                "Reaction could not be saved".into()
+                            }
+                        };
+
+                        addr.do_send(ServerEvent::error(
+                            code,
+                            message,
+                            json!({"id": message_id}),
+                        ));
+                    }
+                }
+            },
+            ctx,
+        );
+    }
+
+    /// Runs an edit or deletion of one of the client's own messages and fans
+    /// the changed message out to both participants.
+    fn change_message(
+        &mut self,
+        id: String,
+        event_type: ServerEventType,
+        ctx: &mut ws::WebsocketContext<Self>,
+        change: impl Future<Output = crate::error::Result<messages::Message>> + 'static,
+    ) {
+        let server_addr = self.server_addr.clone();
+        let addr = ctx.address();
+
+        self.spawn_task(
+            async move {
+                match change.await {
+                    Ok(message) => server_addr.enqueue(actions::ToConversation {
+                        author: message.from,
+                        peer: message.to,
+                        event: ServerEvent::new(event_type, message),
+                    }),
+                    Err(e) => {
+                        let code = e.code();
+                        let message = match e {
+                            Error::BadRequest(reason) | Error::Conflict(reason) => reason,
+                            Error::NotFound => "Message not found".into(),
+                            Error::Forbidden => "You can only change your own messages".into(),
+                            e => {
+                                tracing::error!(error = %e, message_id = %id, "Failed to change message");
+                                "Message could not be changed".into()
+                            }
+                        };
+
+                        addr.do_send(ServerEvent::error(code, message, json!({"id": id})));
+                    }
+                }
+            },
+            ctx,
+        );
+    }
+}
+
+impl actix::Handler<ServerEvent> for WsClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: ServerEvent, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        METRICS.frame_out(msg.event_type);
+
+        match serde_json::to_string(&msg) {
+            Ok(text) => ctx.text(text),
+            Err(e) => tracing::error!(error = %e, "Failed to serialize event"),
         }
     }
 }
 
-impl actix::Handler<GetUID> for WsClient {
-    type Result = String;
+impl actix::Handler<actions::Drain> for WsClient {
+    type Result = ();
 
-    fn handle(&mut self, _msg: GetUID, _ctx: &mut Self::Context) -> Self::Result {
-        self.id.to_string()
+    fn handle(&mut self, _msg: actions::Drain, _ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+
+        self.draining = true;
+        self.report_drained();
+    }
+}
+
+impl actix::Handler<actions::Close> for WsClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::Close, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        close(ctx, msg.code, msg.description);
     }
 }
 
@@ -55,56 +552,167 @@ impl Actor for WsClient {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        self.last_seen = Instant::now();
-        println!("connected {}", self.id);
+        let _span = self.span.clone().entered();
+        tracing::info!("Client connected");
 
-        self.server_addr.do_send(Connect {
-            id: self.id,
-            addr: ctx.address(),
-        });
+        match self.auth {
+            AuthState::Authenticated => self.connect(ctx),
+            _ => {
+                ctx.run_later(AUTH_TIMEOUT, |client, ctx| {
+                    if client.auth != AuthState::Authenticated {
+                        let _span = client.span.clone().entered();
+                        METRICS.auth_failure(&Error::Unauthorized);
+                        close(
+                            ctx,
+                            CLOSE_UNAUTHENTICATED,
+                            "authentication timed out".into(),
+                        );
+                    }
+                });
+            }
+        }
 
         ctx.run_interval(HEARTBEAT_INTERVAL, |client, ctx| {
-            if Instant::now().duration_since(client.last_seen) > CLIENT_TIMEOUT {
-                ctx.stop();
-            } else {
-                client.last_seen = Instant::now();
+            if client.heartbeat.timed_out() {
+                let _span = client.span.clone().entered();
+                tracing::info!("Client missed its heartbeats");
+                METRICS.heartbeat_timeouts.inc();
+                return ctx.stop();
             }
 
-            ctx.ping(b"");
+            ctx.ping(&client.heartbeat.ping_payload());
         });
     }
 
     fn stopped(&mut self, _: &mut Self::Context) {
-        println!("disconnected {}", self.id);
+        let _span = self.span.clone().entered();
+        tracing::info!("Client disconnected");
 
-        self.server_addr.do_send(Disconnect { id: self.id });
+        if self.auth == AuthState::Authenticated {
+            self.server_addr.enqueue(Disconnect {
+                id: self.id,
+                connection_id: self.connection_id,
+            });
+        }
     }
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsClient {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+
+        if msg.is_ok() {
+            self.heartbeat.seen();
+        }
+
         match msg {
+            Ok(ws::Message::Text(_)) if self.draining => {
+                tracing::debug!("Ignoring event while shutting down");
+            }
             Ok(ws::Message::Text(raw)) => {
                 let raw_event: Event = match serde_json::from_str(&raw) {
                     Ok(event) => event,
                     Err(e) => {
-                        tracing::error!("Failed to parse event: {}", e);
+                        METRICS.frame_in(None);
+                        tracing::warn!(error = %e, "Failed to parse event");
                         return;
                     }
                 };
+                METRICS.frame_in(Some(&raw_event.event_type));
+
+                match (&self.auth, &raw_event.event_type) {
+                    (AuthState::Authenticated, _) => {}
+                    (AuthState::Pending, ClientEventType::Auth) => {
+                        match serde_json::from_value::<AuthPayload>(raw_event.data) {
+                            Ok(payload) => self.authenticate(payload, ctx),
+                            Err(e) => {
+                                tracing::warn!(error = %e, "Invalid AUTH payload");
+                                close(ctx, CLOSE_UNAUTHENTICATED, "invalid token".into());
+                            }
+                        }
+                        return;
+                    }
+                    (AuthState::Pending, _) => {
+                        METRICS.auth_failure(&Error::Unauthorized);
+                        return close(ctx, CLOSE_UNAUTHENTICATED, "authentication required".into());
+                    }
+                    (AuthState::Authenticating, _) => {
+                        ctx.notify(ServerEvent::error(
+                            Error::Unauthorized.code(),
+                            "Wait for AUTHENTICATED before sending events",
+                            json!({}),
+                        ));
+                        return;
+                    }
+                }
 
                 match raw_event.event_type {
-                    _ => {}
+                    ClientEventType::SendMessage => {
+                        match serde_json::from_value::<SendMessagePayload>(raw_event.data) {
+                            Ok(payload) => self.send_message(payload, ctx),
+                            Err(e) => tracing::warn!(error = %e, "Invalid SEND_MESSAGE payload"),
+                        }
+                    }
+                    ClientEventType::EditMessage => {
+                        match serde_json::from_value::<EditMessagePayload>(raw_event.data) {
+                            Ok(payload) => self.edit_message(payload, ctx),
+                            Err(e) => tracing::warn!(error = %e, "Invalid EDIT_MESSAGE payload"),
+                        }
+                    }
+                    ClientEventType::DeleteMessage => {
+                        match serde_json::from_value::<DeleteMessagePayload>(raw_event.data) {
+                            Ok(payload) => self.delete_message(payload, ctx),
+                            Err(e) => tracing::warn!(error = %e, "Invalid DELETE_MESSAGE payload"),
+                        }
+                    }
+                    ClientEventType::AddReaction | ClientEventType::RemoveReaction => {
+                        let added = matches!(raw_event.event_type, ClientEventType::AddReaction);
+
+                        match serde_json::from_value::<ReactionPayload>(raw_event.data) {
+                            Ok(payload) => self.react(payload, added, ctx),
+                            Err(e) => tracing::warn!(error = %e, "Invalid reaction payload"),
+                        }
+                    }
+                    ClientEventType::Typing => {
+                        match serde_json::from_value::<TypingPayload>(raw_event.data) {
+                            Ok(payload) => self.server_addr.enqueue(actions::Typing {
+                                from: self.id,
+                                to: payload.to,
+                                typing: payload.typing,
+                            }),
+                            Err(e) => tracing::warn!(error = %e, "Invalid TYPING payload"),
+                        }
+                    }
+                    ClientEventType::Auth => {
+                        tracing::debug!("Ignoring AUTH on an authenticated session");
+                    }
+                    ClientEventType::Reauth => {
+                        match serde_json::from_value::<AuthPayload>(raw_event.data) {
+                            Ok(payload) => self.reauthenticate(payload, ctx),
+                            Err(e) => tracing::warn!(error = %e, "Invalid REAUTH payload"),
+                        }
+                    }
+                    ClientEventType::ChangeMyId => {}
                 }
             }
-            Ok(ws::Message::Pong(_)) => {
-                self.last_seen = Instant::now();
+            Ok(ws::Message::Pong(payload)) => {
+                let Some(quality) = self.heartbeat.pong(&payload) else {
+                    return;
+                };
+                METRICS.rtt.observe(quality.rtt_ms / 1000.0);
+
+                if self.auth == AuthState::Authenticated {
+                    ctx.notify(ServerEvent::new(
+                        ServerEventType::ConnectionQuality,
+                        quality,
+                    ));
+                }
             }
             Ok(ws::Message::Ping(msg)) => {
                 ctx.pong(&msg);
             }
             Err(e) => {
-                tracing::error!("{}", e.to_string())
+                tracing::warn!(error = %e, "Websocket protocol error")
             }
 
             _ => {}
@@ -1,33 +1,155 @@
-use crate::websocket::event::{Connect, Disconnect};
+use crate::metrics::METRICS;
+use crate::websocket::event::{Connect, Disconnect, Roster, ServerEvent, ServerEventType};
 use crate::websocket::{WsClient, actions};
 use actix::{Actor, Addr, Handler, Message};
-use std::collections::HashMap;
+use actix_web_actors::ws::CloseCode;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use uuid::Uuid;
 
-#[derive(Message)]
-#[rtype(result = "Option<Addr<WsClient>>")]
-pub struct GetClient {
-    pub id: Uuid,
+struct Session {
+    addr: Addr<WsClient>,
+    roster: Roster,
+    connection_id: Uuid,
+    connected_at: DateTime<Utc>,
+    remote_addr: Option<String>,
 }
 
-#[derive(Message)]
-#[rtype(result = "Vec<String>")]
-pub struct GetClients;
+/// Sessions that still have events in flight during shutdown.
+struct Draining {
+    pending: HashSet<Uuid>,
+    retry_after: Duration,
+}
 
 #[derive(Default)]
 pub struct Server {
-    sessions: HashMap<Uuid, Addr<WsClient>>,
+    sessions: HashMap<Uuid, Session>,
+    draining: Option<Draining>,
+}
+
+impl Server {
+    fn send_to(&self, id: &Uuid, event: ServerEvent) {
+        if let Some(session) = self.sessions.get(id) {
+            session.addr.do_send(event);
+        }
+    }
+
+    fn presence(id: Uuid, online: bool) -> ServerEvent {
+        ServerEvent::new(
+            ServerEventType::Presence,
+            json!({"id": id, "online": online}),
+        )
+    }
+
+    /// Whether either side has blocked the other. Only sessions that are
+    /// connected are consulted, which is enough since offline clients receive
+    /// nothing anyway.
+    fn blocked_between(&self, a: &Uuid, b: &Uuid) -> bool {
+        let blocks = |x: &Uuid, y: &Uuid| {
+            self.sessions
+                .get(x)
+                .is_some_and(|s| s.roster.blocked.contains(y))
+        };
+
+        blocks(a, b) || blocks(b, a)
+    }
+
+    /// Tell every online contact of `id` whether it is online, and when it is,
+    /// tell `id` which of its contacts are online too.
+    fn broadcast_presence(&self, id: Uuid, online: bool) {
+        let Some(session) = self.sessions.get(&id) else {
+            return;
+        };
+
+        for contact in &session.roster.contacts {
+            if !self.sessions.contains_key(contact) || self.blocked_between(&id, contact) {
+                continue;
+            }
+
+            self.send_to(contact, Self::presence(id, online));
+
+            if online {
+                self.send_to(&id, Self::presence(*contact, true));
+            }
+        }
+    }
+
+    /// Once no session has anything in flight, every delivery they caused is
+    /// already queued on the sessions, so they can all be closed.
+    fn finish_draining(&mut self) {
+        let Some(draining) = self.draining.take_if(|d| d.pending.is_empty()) else {
+            return;
+        };
+
+        for session in self.sessions.values() {
+            session.addr.do_send(actions::Close {
+                code: CloseCode::Restart,
+                description: format!(
+                    "server restarting, retry in {}s",
+                    draining.retry_after.as_secs()
+                ),
+            });
+        }
+    }
+
+    fn accepts_from(&self, to: &Uuid, from: &Uuid) -> bool {
+        !self.blocked_between(to, from)
+            && self
+                .sessions
+                .get(to)
+                .is_some_and(|s| !s.roster.contacts_only || s.roster.contacts.contains(from))
+    }
 }
 
 impl Actor for Server {
     type Context = actix::Context<Self>;
 }
 
-impl Handler<GetClients> for Server {
-    type Result = Vec<String>;
+/// Fire-and-forget messages the `Server` actor handles.
+pub trait Routed: Message<Result = ()> + Send + 'static {
+    fn route(self, server: &mut Server, ctx: &mut actix::Context<Server>);
+}
+
+impl<M> Routed for M
+where
+    M: Message<Result = ()> + Send + 'static,
+    Server: Handler<M, Result = ()>,
+{
+    fn route(self, server: &mut Server, ctx: &mut actix::Context<Server>) {
+        Handler::<M>::handle(server, self, ctx)
+    }
+}
+
+/// A message on its way to the `Server` actor. It counts towards the mailbox
+/// depth metric until it is handled.
+pub struct Queued<M>(M);
+
+impl<M: Routed> Message for Queued<M> {
+    type Result = ();
+}
+
+impl<M: Routed> Handler<Queued<M>> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: Queued<M>, ctx: &mut Self::Context) {
+        METRICS.server_mailbox.dec();
+        msg.0.route(self, ctx)
+    }
+}
+
+/// Use this rather than `do_send` so the message shows up in the mailbox
+/// depth. Requests that wait for a reply go through `send` and are not
+/// counted.
+pub trait Enqueue {
+    fn enqueue<M: Routed>(&self, msg: M);
+}
 
-    fn handle(&mut self, _msg: GetClients, _ctx: &mut Self::Context) -> Self::Result {
-        self.sessions.keys().map(|id| id.to_string()).collect()
+impl Enqueue for Addr<Server> {
+    fn enqueue<M: Routed>(&self, msg: M) {
+        METRICS.server_mailbox.inc();
+        self.do_send(Queued(msg));
     }
 }
 
@@ -43,27 +165,311 @@ impl Handler<GetClients> for Server {
 //     }
 // }
 
-impl Handler<GetClient> for Server {
-    type Result = Option<Addr<WsClient>>;
-
-    fn handle(&mut self, msg: GetClient, _ctx: &mut Self::Context) -> Self::Result {
-        self.sessions.get(&msg.id).cloned()
-    }
-}
-
 impl Handler<Connect> for Server {
     type Result = ();
 
-    fn handle(&mut self, msg: Connect, ctx: &mut Self::Context) {
+    fn handle(&mut self, msg: Connect, _ctx: &mut Self::Context) {
         let id = msg.id;
-        self.sessions.insert(id, msg.addr);
+        self.sessions.insert(
+            id,
+            Session {
+                addr: msg.addr,
+                roster: msg.roster,
+                connection_id: msg.connection_id,
+                connected_at: Utc::now(),
+                remote_addr: msg.remote_addr,
+            },
+        );
+        METRICS.sessions.set(self.sessions.len() as i64);
+
+        self.broadcast_presence(id, true);
     }
 }
 
 impl Handler<Disconnect> for Server {
     type Result = ();
 
-    fn handle(&mut self, msg: Disconnect, ctx: &mut Self::Context) {
+    fn handle(&mut self, msg: Disconnect, _ctx: &mut Self::Context) {
+        // A client that reconnected before its old socket stopped already has
+        // a newer session, which must stay.
+        if self
+            .sessions
+            .get(&msg.id)
+            .is_none_or(|s| s.connection_id != msg.connection_id)
+        {
+            return;
+        }
+
+        self.broadcast_presence(msg.id, false);
         self.sessions.remove(&msg.id);
+        METRICS.sessions.set(self.sessions.len() as i64);
+
+        if let Some(draining) = &mut self.draining
+            && draining.pending.remove(&msg.id)
+        {
+            self.finish_draining();
+        }
+    }
+}
+
+impl Handler<actions::Notify> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::Notify, _ctx: &mut Self::Context) {
+        self.send_to(&msg.to, msg.event);
+    }
+}
+
+impl Handler<actions::ContactAdded> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::ContactAdded, _ctx: &mut Self::Context) {
+        for (id, contact) in [(msg.a, msg.b), (msg.b, msg.a)] {
+            if let Some(session) = self.sessions.get_mut(&id) {
+                session.roster.contacts.insert(contact);
+            }
+        }
+
+        if self.sessions.contains_key(&msg.a)
+            && self.sessions.contains_key(&msg.b)
+            && !self.blocked_between(&msg.a, &msg.b)
+        {
+            for (id, contact) in [(msg.a, msg.b), (msg.b, msg.a)] {
+                self.send_to(&id, Self::presence(contact, true));
+            }
+        }
+    }
+}
+
+impl Handler<actions::ContactRemoved> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::ContactRemoved, _ctx: &mut Self::Context) {
+        for (id, contact) in [(msg.a, msg.b), (msg.b, msg.a)] {
+            if let Some(session) = self.sessions.get_mut(&id) {
+                session.roster.contacts.remove(&contact);
+            }
+        }
+
+        // Presence is only shared between contacts, so each side stops
+        // seeing the other online.
+        if self.sessions.contains_key(&msg.a)
+            && self.sessions.contains_key(&msg.b)
+            && !self.blocked_between(&msg.a, &msg.b)
+        {
+            for (id, contact) in [(msg.a, msg.b), (msg.b, msg.a)] {
+                self.send_to(&id, Self::presence(contact, false));
+            }
+        }
+    }
+}
+
+impl Handler<actions::SetContactsOnly> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::SetContactsOnly, _ctx: &mut Self::Context) {
+        if let Some(session) = self.sessions.get_mut(&msg.id) {
+            session.roster.contacts_only = msg.contacts_only;
+        }
+    }
+}
+
+impl Handler<actions::Deliver> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::Deliver, _ctx: &mut Self::Context) {
+        let message = msg.message;
+
+        if !self.accepts_from(&message.to, &message.from) {
+            return;
+        }
+
+        let muted = msg.root_author != Some(message.to)
+            && self
+                .sessions
+                .get(&message.to)
+                .is_some_and(|s| s.roster.muted.contains(&message.from));
+
+        let mut data = serde_json::to_value(&message).unwrap_or_default();
+        data["muted"] = muted.into();
+
+        self.send_to(
+            &message.to,
+            ServerEvent::new(ServerEventType::MessageReceived, data),
+        );
+        METRICS
+            .routing_latency
+            .observe(msg.received_at.elapsed().as_secs_f64());
+    }
+}
+
+impl Handler<actions::ToConversation> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::ToConversation, _ctx: &mut Self::Context) {
+        if !self.blocked_between(&msg.author, &msg.peer) {
+            self.send_to(&msg.peer, msg.event.clone());
+        }
+
+        self.send_to(&msg.author, msg.event);
+    }
+}
+
+impl Handler<actions::Typing> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::Typing, _ctx: &mut Self::Context) {
+        if self.accepts_from(&msg.to, &msg.from) {
+            self.send_to(
+                &msg.to,
+                ServerEvent::new(
+                    ServerEventType::Typing,
+                    json!({"from": msg.from, "typing": msg.typing}),
+                ),
+            );
+        }
+    }
+}
+
+impl Handler<actions::BlockChanged> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::BlockChanged, _ctx: &mut Self::Context) {
+        if !self.sessions.contains_key(&msg.id) {
+            return;
+        }
+
+        let were_visible = !self.blocked_between(&msg.id, &msg.other);
+
+        if let Some(session) = self.sessions.get_mut(&msg.id) {
+            if msg.blocked {
+                session.roster.blocked.insert(msg.other);
+            } else {
+                session.roster.blocked.remove(&msg.other);
+            }
+        }
+
+        let visible = !self.blocked_between(&msg.id, &msg.other);
+        let are_contacts = self
+            .sessions
+            .get(&msg.id)
+            .is_some_and(|s| s.roster.contacts.contains(&msg.other));
+
+        if were_visible != visible && are_contacts && self.sessions.contains_key(&msg.other) {
+            self.send_to(&msg.id, Self::presence(msg.other, visible));
+            self.send_to(&msg.other, Self::presence(msg.id, visible));
+        }
+    }
+}
+
+impl Handler<actions::MuteChanged> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::MuteChanged, _ctx: &mut Self::Context) {
+        if let Some(session) = self.sessions.get_mut(&msg.id) {
+            if msg.muted {
+                session.roster.muted.insert(msg.peer);
+            } else {
+                session.roster.muted.remove(&msg.peer);
+            }
+        }
+    }
+}
+
+impl Handler<actions::GetOnline> for Server {
+    type Result = actix::MessageResult<actions::GetOnline>;
+
+    fn handle(&mut self, msg: actions::GetOnline, _ctx: &mut Self::Context) -> Self::Result {
+        actix::MessageResult(
+            msg.ids
+                .into_iter()
+                .filter(|id| self.sessions.contains_key(id))
+                .collect::<HashSet<_>>(),
+        )
+    }
+}
+
+impl Handler<actions::ProfileUpdated> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::ProfileUpdated, _ctx: &mut Self::Context) {
+        let event = ServerEvent::new(
+            ServerEventType::ProfileUpdated,
+            json!({"id": msg.id, "profile": msg.profile}),
+        );
+
+        for (id, session) in &self.sessions {
+            if session.roster.contacts.contains(&msg.id) && !self.blocked_between(id, &msg.id) {
+                session.addr.do_send(event.clone());
+            }
+        }
+    }
+}
+
+impl Handler<actions::Probe> for Server {
+    type Result = ();
+
+    fn handle(&mut self, _msg: actions::Probe, _ctx: &mut Self::Context) {}
+}
+
+impl Handler<actions::Shutdown> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::Shutdown, _ctx: &mut Self::Context) {
+        for session in self.sessions.values() {
+            session.addr.do_send(actions::Drain);
+        }
+
+        self.draining = Some(Draining {
+            pending: self.sessions.keys().copied().collect(),
+            retry_after: msg.retry_after,
+        });
+        self.finish_draining();
+    }
+}
+
+impl Handler<actions::Drained> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: actions::Drained, _ctx: &mut Self::Context) {
+        if let Some(draining) = &mut self.draining {
+            draining.pending.remove(&msg.id);
+        }
+
+        self.finish_draining();
+    }
+}
+
+impl Handler<actions::ListSessions> for Server {
+    type Result = actix::MessageResult<actions::ListSessions>;
+
+    fn handle(&mut self, _msg: actions::ListSessions, _ctx: &mut Self::Context) -> Self::Result {
+        actix::MessageResult(
+            self.sessions
+                .iter()
+                .map(|(id, session)| actions::SessionInfo {
+                    id: *id,
+                    connection_id: session.connection_id,
+                    connected_at: session.connected_at,
+                    remote_addr: session.remote_addr.clone(),
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Handler<actions::Kick> for Server {
+    type Result = bool;
+
+    fn handle(&mut self, msg: actions::Kick, _ctx: &mut Self::Context) -> bool {
+        let Some(session) = self.sessions.get(&msg.id) else {
+            return false;
+        };
+
+        session.addr.do_send(actions::Close {
+            code: msg.code,
+            description: msg.reason,
+        });
+        true
     }
 }